@@ -0,0 +1,232 @@
+//! The effects that are applied to the voices: EQ, chorus, reverb and a
+//! limiter on the output.
+
+use fx::biquad::{BiquadFilterType, StereoBiquadFilter};
+use fx::delay_line::StereoDelay;
+use fx::freeverb::Freeverb;
+use fx::limiter::Limiter;
+use fx::params::{percentage, ReverbParams};
+use nih_plug::prelude::*;
+
+/// The corner frequency of the low shelf in Hz.
+const EQ_LOW_FREQUENCY: f32 = 200.0;
+/// The corner frequency of the high shelf in Hz.
+const EQ_HIGH_FREQUENCY: f32 = 5000.0;
+/// The Q of the EQ bands.
+const EQ_Q: f32 = 0.7;
+/// The longest delay the chorus sweeps over, in seconds.
+const CHORUS_MAX_WIDTH_S: f32 = 0.008;
+/// The delay buffer of the chorus, in seconds. It has some room to spare over
+/// the widest sweep.
+const CHORUS_BUFFER_S: f32 = 0.05;
+/// How far the right channel's LFO is ahead of the left one, in cycles.
+const CHORUS_STEREO_PHASE: f32 = 0.25;
+
+#[derive(Params)]
+pub struct EffectsParams {
+    #[id = "eq"]
+    pub eq_enabled: BoolParam,
+    #[id = "eq-low"]
+    pub eq_low: FloatParam,
+    #[id = "eq-mid"]
+    pub eq_mid: FloatParam,
+    #[id = "eq-mid-freq"]
+    pub eq_mid_frequency: FloatParam,
+    #[id = "eq-high"]
+    pub eq_high: FloatParam,
+
+    #[id = "chorus"]
+    pub chorus_enabled: BoolParam,
+    #[id = "chorus-rate"]
+    pub chorus_rate: FloatParam,
+    #[id = "chorus-depth"]
+    pub chorus_depth: FloatParam,
+    #[id = "chorus-mix"]
+    pub chorus_mix: FloatParam,
+
+    #[nested]
+    pub reverb: ReverbParams,
+
+    #[id = "limiter"]
+    pub limiter_enabled: BoolParam,
+    #[id = "limiter-threshold"]
+    pub limiter_threshold: FloatParam,
+    #[id = "limiter-release"]
+    pub limiter_release: FloatParam,
+}
+
+impl Default for EffectsParams {
+    fn default() -> Self {
+        let eq_gain = |name: &str| {
+            FloatParam::new(
+                name,
+                0.0,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 12.0,
+                },
+            )
+            .with_step_size(0.1)
+            .with_unit(" dB")
+        };
+
+        Self {
+            eq_enabled: BoolParam::new("EQ", false),
+            eq_low: eq_gain("EQ Low"),
+            eq_mid: eq_gain("EQ Mid"),
+            eq_mid_frequency: FloatParam::new(
+                "EQ Mid Frequency",
+                1000.0,
+                FloatRange::Skewed {
+                    min: 300.0,
+                    max: 4000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            eq_high: eq_gain("EQ High"),
+
+            chorus_enabled: BoolParam::new("Chorus", false),
+            chorus_rate: FloatParam::new(
+                "Chorus Rate",
+                0.8,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 5.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" Hz"),
+            chorus_depth: percentage("Chorus Depth", 0.5),
+            chorus_mix: percentage("Chorus Mix", 0.5),
+
+            reverb: ReverbParams::default(),
+
+            limiter_enabled: BoolParam::new("Limiter", false),
+            limiter_threshold: FloatParam::new(
+                "Limiter Threshold",
+                -0.3,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 0.0,
+                },
+            )
+            .with_step_size(0.1)
+            .with_unit(" dB"),
+            limiter_release: FloatParam::new(
+                "Limiter Release",
+                0.1,
+                FloatRange::Skewed {
+                    min: 0.01,
+                    max: 1.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" s"),
+        }
+    }
+}
+
+/// The state of the effects at a sample rate.
+pub struct Effects {
+    sample_rate: f32,
+    eq_low: StereoBiquadFilter,
+    eq_mid: StereoBiquadFilter,
+    eq_high: StereoBiquadFilter,
+    chorus: StereoDelay,
+    reverb: Freeverb,
+    limiter: Limiter,
+}
+
+impl Effects {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            eq_low: StereoBiquadFilter::new(),
+            eq_mid: StereoBiquadFilter::new(),
+            eq_high: StereoBiquadFilter::new(),
+            chorus: StereoDelay::new(CHORUS_BUFFER_S, sample_rate as usize),
+            reverb: Freeverb::new(sample_rate as usize),
+            limiter: Limiter::new(sample_rate),
+        }
+    }
+
+    /// Clears all delay lines and filters.
+    pub fn reset(&mut self) {
+        self.eq_low.reset();
+        self.eq_mid.reset();
+        self.eq_high.reset();
+        self.chorus.reset();
+        self.reverb.reset();
+        self.limiter.reset();
+    }
+
+    /// Updates the effects from the parameters. This is done once per block,
+    /// since recalculating the filters for every sample is expensive.
+    pub fn update(&mut self, params: &EffectsParams) {
+        if params.eq_enabled.value() {
+            let frequency = |hz: f32| hz / self.sample_rate;
+            self.eq_low.set_biquads(
+                BiquadFilterType::LowShelf,
+                frequency(EQ_LOW_FREQUENCY),
+                EQ_Q,
+                params.eq_low.value(),
+            );
+            self.eq_mid.set_biquads(
+                BiquadFilterType::ParametricEQ,
+                frequency(params.eq_mid_frequency.value()),
+                EQ_Q,
+                params.eq_mid.value(),
+            );
+            self.eq_high.set_biquads(
+                BiquadFilterType::HighShelf,
+                frequency(EQ_HIGH_FREQUENCY),
+                EQ_Q,
+                params.eq_high.value(),
+            );
+        }
+
+        params.reverb.update(&mut self.reverb);
+
+        self.limiter.set_parameters(
+            params.limiter_threshold.value(),
+            params.limiter_release.value(),
+        );
+    }
+
+    /// Runs a mono sample through the enabled effects, which makes it stereo.
+    /// The gain is applied before the limiter, so the limiter catches it.
+    pub fn process(&mut self, params: &EffectsParams, input: f32, gain: f32) -> (f32, f32) {
+        let mut output = (input, input);
+
+        if params.eq_enabled.value() {
+            output = self.eq_low.process(output);
+            output = self.eq_mid.process(output);
+            output = self.eq_high.process(output);
+        }
+
+        if params.chorus_enabled.value() {
+            // The chorus adds the delayed signal to the dry one, scaled by the
+            // mix.
+            output = self.chorus.process_with_chorus(
+                output,
+                params.chorus_rate.value(),
+                params.chorus_depth.value() * CHORUS_MAX_WIDTH_S,
+                CHORUS_STEREO_PHASE,
+                params.chorus_mix.value(),
+                0.0,
+            );
+        }
+
+        output = params.reverb.process(&mut self.reverb, output);
+
+        output = (output.0 * gain, output.1 * gain);
+
+        if params.limiter_enabled.value() {
+            output = self.limiter.process(output);
+        }
+
+        output
+    }
+}
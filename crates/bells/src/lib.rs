@@ -4,26 +4,48 @@ use std::sync::{
     Arc,
 };
 
-use engine::{Adsr, Voice};
-use instrument::Instrument;
+use effects::{Effects, EffectsParams};
+use engine::library::LibraryParams;
+use engine::{Adsr, InstrumentSlot, Task, Voice, Voices};
+use instrument::cache::{self, InstrumentCache};
+use instrument::metadata::AmpEnvelope;
+use instrument::{legacy, library, Instrument};
 use nih_plug::prelude::*;
 use presets::Presets;
+use strike::{NoteOffMode, Retrigger};
 
-pub mod instrument;
+mod effects;
 mod presets;
+mod strike;
 
 const DEFAULT_ATTACK_S: f32 = 0.01;
 const DEFAULT_DECAY_S: f32 = 0.1;
 const DEFAULT_SUSTAIN_LEVEL: f32 = 1.0;
 const DEFAULT_RELEASE_S: f32 = 0.2;
-const ORIGINAL_SAMPLE_RATE: f32 = 44100.0;
+/// How long the voices of the previous instrument take to fade out after
+/// switching instruments.
+const FADE_OUT_S: f32 = 0.02;
+const DEFAULT_DAMPING_S: f32 = 0.3;
+/// The most voices that ring at once.
+const MAX_VOICES: usize = 64;
 
 struct Bells {
     params: Arc<BellsParams>,
-    voices: Vec<Voice>,
-    instrument: Instrument,
+    voices: Voices,
+    slot: InstrumentSlot,
+    /// Instruments that were already resampled to a sample rate.
+    cache: Arc<InstrumentCache>,
     sample_rate: f32,
     adsr: Adsr,
+    effects: Effects,
+}
+
+/// Loads the instrument and brings it to the sample rate. With `select` the
+/// library path is updated from the selection first, which isn't done when
+/// the state is restored.
+pub struct Load {
+    sample_rate: f32,
+    select: bool,
 }
 
 #[derive(Params)]
@@ -38,10 +60,26 @@ struct BellsParams {
     pub sustain: FloatParam,
     #[id = "release"]
     pub release: FloatParam,
+    /// What happens when a key is let go.
+    #[id = "note-off"]
+    pub note_off_mode: EnumParam<NoteOffMode>,
+    /// How long a damped bell takes to die out.
+    #[id = "damping"]
+    pub damping: FloatParam,
+    #[id = "retrigger"]
+    pub retrigger: EnumParam<Retrigger>,
+    /// How loud the ringing bells at harmonic intervals from a struck one
+    /// ring along with it.
+    #[id = "sympathetic"]
+    pub sympathetic: FloatParam,
     #[id = "preset"]
     pub preset: EnumParam<Presets>,
+    #[nested]
+    pub library: LibraryParams,
     // This flag is used to signal the audio thread that the preset has changed.
     pub preset_change: Arc<AtomicBool>,
+    #[nested(group = "Effects")]
+    pub effects: EffectsParams,
 }
 
 impl Default for Bells {
@@ -50,10 +88,12 @@ impl Default for Bells {
 
         Self {
             params: Arc::new(BellsParams::default()),
-            voices: Vec::new(),
-            instrument: Instrument::default(),
+            voices: Voices::new(MAX_VOICES),
+            slot: InstrumentSlot::new(),
+            cache: Arc::new(InstrumentCache::new(cache::user_cache_dir(Self::NAME))),
             sample_rate,
             adsr: Adsr::new(sample_rate),
+            effects: Effects::new(sample_rate),
         }
     }
 }
@@ -113,13 +153,35 @@ impl Default for BellsParams {
                 },
             )
             .with_unit(" s"),
+            note_off_mode: EnumParam::new("Note Off", NoteOffMode::Release),
+            damping: FloatParam::new(
+                "Damping",
+                DEFAULT_DAMPING_S,
+                FloatRange::Skewed {
+                    min: 0.02,
+                    max: 5.0,
+                    factor: 0.25,
+                },
+            )
+            .with_unit(" s"),
+            retrigger: EnumParam::new("Retrigger", Retrigger::NewVoice),
+            sympathetic: FloatParam::new(
+                "Sympathetic Ringing",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
             preset: EnumParam::new("Preset", Presets::default()).with_callback({
                 let preset_change = preset_change.clone();
                 Arc::new(move |_| {
                     preset_change.store(true, Ordering::Relaxed);
                 })
             }),
+            library: LibraryParams::new(Bells::NAME, preset_change.clone()),
             preset_change,
+            effects: EffectsParams::default(),
         }
     }
 }
@@ -149,30 +211,68 @@ impl Plugin for Bells {
 
     type SysExMessage = ();
 
-    type BackgroundTask = ();
+    type BackgroundTask = Task<Load>;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let loaded = self.slot.loaded.clone();
+        let cache = self.cache.clone();
+
+        Box::new(move |task| {
+            task.run(
+                &loaded,
+                |Load {
+                     sample_rate,
+                     select,
+                 }| {
+                    if select {
+                        params.library.select();
+                    }
+                    params.load_instrument(&cache, sample_rate)
+                },
+            )
+        })
+    }
+
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
-        if self.instrument.samples.is_empty() || self.sample_rate != buffer_config.sample_rate {
+        // Restoring the state also triggers the parameter callbacks, but then
+        // the persisted library path is loaded rather than the selection.
+        let changed = self.params.preset_change.swap(false, Ordering::Relaxed);
+        if changed
+            || self.slot.instrument.zones.is_empty()
+            || self.sample_rate != buffer_config.sample_rate
+        {
             self.sample_rate = buffer_config.sample_rate;
-            self.load_preset(self.params.preset.value());
+            // This isn't the audio thread, so the instrument is loaded right
+            // away and playback starts with it.
+            context.execute(Task::Load(Load {
+                sample_rate: self.sample_rate,
+                select: false,
+            }));
+            self.slot.replace_loaded(&mut [&mut self.voices]);
         }
 
-        self.adsr = Adsr::new(self.sample_rate);
+        // The envelope times are in seconds, so they're kept.
+        self.adsr.set_sample_rate(self.sample_rate);
+        // The delay lines are sized for the sample rate.
+        self.effects = Effects::new(self.sample_rate);
 
         true
     }
 
     fn reset(&mut self) {
         self.voices.clear();
+        self.effects.reset();
+        self.params.gain.smoothed.reset(self.params.gain.value());
     }
 
     fn process(
@@ -181,15 +281,32 @@ impl Plugin for Bells {
         _aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        // Swap in an instrument that was loaded in the background.
+        let fade_out_samples = (FADE_OUT_S * self.sample_rate) as u32;
+        if let Some(instrument) = self
+            .slot
+            .swap_loaded(&mut [&mut self.voices], fade_out_samples)
+        {
+            context.execute_background(Task::Drop(instrument));
+        }
+
         let mut next_event = context.next_event();
 
-        // Update ADSR parameters from the plugin's state.
+        // Update ADSR parameters from the plugin's state, unless the instrument
+        // was imported with an amp envelope, which it's played with instead.
+        let envelope = AmpEnvelope::read(&self.slot.instrument.metadata).unwrap_or(AmpEnvelope {
+            attack: self.params.attack.value(),
+            decay: self.params.decay.value(),
+            sustain: self.params.sustain.value(),
+            release: self.params.release.value(),
+        });
         self.adsr.set_parameters(
-            self.params.attack.value(),
-            self.params.decay.value(),
-            self.params.sustain.value(),
-            self.params.release.value(),
+            envelope.attack,
+            envelope.decay,
+            envelope.sustain,
+            envelope.release,
         );
+        self.effects.update(&self.params.effects);
 
         for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
             // Process MIDI events for this sample.
@@ -198,25 +315,8 @@ impl Plugin for Bells {
                     break;
                 }
                 match event {
-                    NoteEvent::NoteOn { note, velocity, .. } => {
-                        if let Some(data) = self.instrument.samples.get(&note) {
-                            // Cloning the Arc is cheap (it just increments a reference count).
-                            let new_voice = Voice::new(
-                                Arc::clone(data),
-                                note,
-                                velocity,
-                                self.adsr.clone(),
-                                false,
-                            );
-                            self.voices.push(new_voice);
-                        }
-                    }
-                    NoteEvent::NoteOff { note, .. } => {
-                        self.voices
-                            .iter_mut()
-                            .filter(|v| v.matches_note(note))
-                            .for_each(|v| v.note_off());
-                    }
+                    NoteEvent::NoteOn { note, velocity, .. } => self.strike(note, velocity),
+                    NoteEvent::NoteOff { note, .. } => self.release(note),
                     _ => (),
                 }
                 next_event = context.next_event();
@@ -226,23 +326,32 @@ impl Plugin for Bells {
             let gain = self.params.gain.smoothed.next();
 
             // Sum the output of all active voices.
-            let mut output_sample = 0.0;
-            for voice in &mut self.voices {
-                output_sample += voice.next_sample();
-            }
+            let output_sample = self.voices.next_sample();
 
-            // Write the final sample to all channels.
-            for sample in channel_samples {
-                *sample = output_sample * gain;
+            let (left, right) = self
+                .effects
+                .process(&self.params.effects, output_sample, gain);
+
+            // Write the final sample to the channels, left and right.
+            for (channel, sample) in channel_samples.into_iter().enumerate() {
+                *sample = if channel % 2 == 0 { left } else { right };
             }
         }
 
         // Remove voices that are no longer active.
-        self.voices.retain(|v| v.is_active());
+        self.voices.remove_finished();
+
+        // Once the voices of the previous instrument are gone, it can go too.
+        if let Some(instrument) = self.slot.take_retired(&[&self.voices]) {
+            context.execute_background(Task::Drop(instrument));
+        }
 
         // Check if the preset has been changed on the GUI thread.
         if self.params.preset_change.swap(false, Ordering::Relaxed) {
-            self.load_preset(self.params.preset.value());
+            context.execute_background(Task::Load(Load {
+                sample_rate: self.sample_rate,
+                select: true,
+            }));
         }
 
         ProcessStatus::Normal
@@ -250,35 +359,126 @@ impl Plugin for Bells {
 }
 
 impl Bells {
-    pub fn load_preset(&mut self, preset: Presets) {
-        self.voices.clear();
+    /// Strikes the bell of a note, and lets the bells that are still ringing
+    /// at harmonic intervals from it ring along.
+    fn strike(&mut self, note: u8, velocity: f32) {
+        if self.params.retrigger.value() == Retrigger::Restrike {
+            let fade_out_samples = (strike::RESTRIKE_FADE_S * self.sample_rate) as u32;
+            self.voices
+                .iter_mut()
+                .filter(|v| v.matches_note(note))
+                .for_each(|v| v.fade_out(fade_out_samples));
+        }
 
-        let instrument_data = preset.content().to_vec();
-
-        // Step 1: Decode the instrument data.
-        let mut instrument = std::thread::spawn(move || Instrument::decode(instrument_data))
-            .join()
-            .expect("Failed to load preset on a different thread");
-
-        if self.sample_rate != ORIGINAL_SAMPLE_RATE {
-            instrument.samples = instrument
-                .samples
-                .into_iter()
-                .map(|(k, v)| {
-                    (
-                        k,
-                        Arc::new(common::resampler::resample(
-                            &v,
-                            ORIGINAL_SAMPLE_RATE,
-                            self.sample_rate,
-                        )),
-                    )
-                })
-                .collect();
+        self.start_voice(note, note, velocity);
+
+        let sympathetic = self.params.sympathetic.value();
+        if sympathetic > 0.0 {
+            for voice in self
+                .voices
+                .iter_mut()
+                .filter(|v| !v.is_fading() && !v.is_damped())
+            {
+                let interval = voice.note() as i32 - note as i32;
+                if let Some((_, level)) = strike::SYMPATHETIC_INTERVALS
+                    .iter()
+                    .find(|(i, _)| *i == interval)
+                {
+                    voice.excite(velocity * sympathetic * level);
+                }
+            }
+        }
+    }
+
+    /// Starts a voice for a key that plays the bell at `pitch`.
+    fn start_voice(&mut self, note: u8, pitch: u8, velocity: f32) {
+        let instrument = &self.slot.instrument;
+        if let Some(zone) = instrument.find_zone(pitch, velocity) {
+            let new_voice = Voice::from_zone(
+                instrument,
+                zone,
+                note,
+                pitch as f64,
+                velocity,
+                self.adsr.clone(),
+                self.sample_rate,
+            );
+            self.voices
+                .start(new_voice, (FADE_OUT_S * self.sample_rate) as u32);
+        }
+    }
+
+    /// Lets go of a key.
+    fn release(&mut self, note: u8) {
+        let voices = self.voices.iter_mut().filter(|v| v.matches_note(note));
+        match self.params.note_off_mode.value() {
+            NoteOffMode::Release => voices.for_each(|v| v.note_off()),
+            NoteOffMode::Damper => {
+                let damping_samples = (self.params.damping.value() * self.sample_rate) as u32;
+                voices.for_each(|v| v.damp(damping_samples));
+            }
+            NoteOffMode::Ring => (),
         }
+    }
+}
+
+impl BellsParams {
+    /// Loads the user instrument from the library, or the preset if there is
+    /// none or it can't be loaded, at the given sample rate.
+    fn load_instrument(&self, cache: &InstrumentCache, sample_rate: f32) -> Option<Instrument> {
+        let user_instrument = self.library.load(|path| {
+            let key = cache::file_key(path)?;
+            cache.get_or_insert_with(&key, sample_rate as u32, || {
+                library::load(path).map(|instrument| resample(instrument, sample_rate))
+            })
+        });
+        if user_instrument.is_some() {
+            return user_instrument;
+        }
+
+        // The presets are compiled into the plugin, so they only change with
+        // its version.
+        let preset = self.preset.value();
+        let key = format!("preset/{}/{}", Bells::VERSION, preset);
+        let instrument = cache.get_or_insert_with(&key, sample_rate as u32, || {
+            legacy::decode_bells(preset.content())
+                .map(|instrument| resample(instrument, sample_rate))
+        });
+        match instrument {
+            Ok(instrument) => Some(instrument),
+            Err(e) => {
+                nih_error!("Failed to decode the {} preset: {}", preset, e);
+                None
+            }
+        }
+    }
+}
 
-        self.instrument = instrument;
+/// Brings every zone of an instrument to the host's sample rate.
+fn resample(mut instrument: Instrument, sample_rate: f32) -> Instrument {
+    let original_sample_rate = instrument.sample_rate as f32;
+    if sample_rate != original_sample_rate {
+        let ratio = sample_rate as f64 / original_sample_rate as f64;
+        let channels = instrument.channels.max(1) as usize;
+        for zone in &mut instrument.zones {
+            zone.data = Arc::new(common::resampler::resample_interleaved(
+                &zone.data,
+                channels,
+                original_sample_rate,
+                sample_rate,
+                common::resampler::Quality::default(),
+            ));
+            // The loop has to cover the same part of the sample at the new
+            // rate.
+            if let Some(loop_points) = &mut zone.loop_points {
+                loop_points.start = (loop_points.start as f64 * ratio).round() as u32;
+                loop_points.end = (loop_points.end as f64 * ratio).round() as u32;
+            }
+        }
+        instrument.sample_rate = sample_rate as u32;
     }
+
+    instrument
 }
 
 impl ClapPlugin for Bells {
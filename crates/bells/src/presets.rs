@@ -13,7 +13,7 @@ pub const PLASTIC: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "bells/plasti
 impl Presets {
     /// Returns the compressed instrument data for the selected preset.
     /// with the corresponding `.bin` files.
-    pub fn content(&self) -> &[u8] {
+    pub fn content(&self) -> &'static [u8] {
         match self {
             Presets::Brass => BRASS,
             Presets::Plastic => PLASTIC,
@@ -0,0 +1,40 @@
+//! How bells respond to being struck and let go, beyond the envelope.
+
+use nih_plug::prelude::Enum;
+
+/// How long a ringing bell takes to fade out when it's struck again, in
+/// seconds.
+pub const RESTRIKE_FADE_S: f32 = 0.005;
+/// The intervals in semitones at which a ringing bell rings along with a
+/// struck one, and how strongly it's excited relative to the strike. These
+/// are the octaves and twelfths, where the partials of the bells line up.
+pub const SYMPATHETIC_INTERVALS: [(i32, f32); 6] = [
+    (-24, 0.25),
+    (-19, 0.35),
+    (-12, 0.5),
+    (12, 1.0),
+    (19, 0.7),
+    (24, 0.5),
+];
+
+/// What happens when a key is let go.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum NoteOffMode {
+    /// The envelope is released.
+    Release,
+    /// A damper is put on the bell, which dies out quickly and loses its
+    /// highs first.
+    Damper,
+    /// The bell rings out.
+    Ring,
+}
+
+/// What happens when a bell that is still ringing is struck again.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum Retrigger {
+    /// The bell rings on and another one is struck.
+    #[name = "New Voice"]
+    NewVoice,
+    /// The same bell is struck again, which cuts its ringing short.
+    Restrike,
+}
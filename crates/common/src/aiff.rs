@@ -0,0 +1,253 @@
+use crate::audio::{AudioError, AudioFile, SampleMetadata};
+
+/// The encoding of the sample data in an AIFF or AIFF-C `SSND` chunk.
+#[derive(Clone, Debug, Copy, Eq, PartialEq)]
+enum Encoding {
+    /// Big-endian two's complement integers (`AIFF` and AIFF-C `NONE`).
+    IntBigEndian,
+    /// Little-endian two's complement integers (AIFF-C `sowt`).
+    IntLittleEndian,
+    /// Big-endian IEEE 754 single precision floats (AIFF-C `fl32`).
+    Float32,
+    /// Big-endian IEEE 754 double precision floats (AIFF-C `fl64`).
+    Float64,
+}
+
+/// The contents of the `COMM` chunk.
+struct Common {
+    channels: u16,
+    frames: u32,
+    bits_per_sample: u16,
+    sample_rate: f64,
+    encoding: Encoding,
+}
+
+/// Decodes an AIFF or AIFF-C file into interleaved f32 samples.
+///
+/// Only uncompressed encodings are supported, which covers the files
+/// produced by every sample editor we use.
+pub(crate) fn decode(bytes: &[u8]) -> Result<AudioFile, AudioError> {
+    if bytes.len() < 12 || &bytes[0..4] != b"FORM" {
+        return Err(AudioError::Malformed("missing FORM header"));
+    }
+
+    let is_aifc = match &bytes[8..12] {
+        b"AIFF" => false,
+        b"AIFC" => true,
+        _ => return Err(AudioError::Malformed("not an AIFF or AIFF-C file")),
+    };
+
+    let mut common = None;
+    let mut sound_data = None;
+
+    for (id, body) in chunks(&bytes[12..]) {
+        match &id {
+            b"COMM" => common = Some(parse_common(body, is_aifc)?),
+            b"SSND" => {
+                if body.len() < 8 {
+                    return Err(AudioError::Malformed("SSND chunk is too short"));
+                }
+                let offset = read_u32_be(body, 0) as usize;
+                sound_data = Some(body.get(8 + offset..).unwrap_or_default());
+            }
+            _ => (),
+        }
+    }
+
+    let common = common.ok_or(AudioError::Malformed("missing COMM chunk"))?;
+    let sound_data = sound_data.unwrap_or_default();
+
+    if common.channels == 0 {
+        return Err(AudioError::Malformed("COMM chunk declares zero channels"));
+    }
+
+    let bytes_per_sample = match common.encoding {
+        Encoding::Float32 => 4,
+        Encoding::Float64 => 8,
+        Encoding::IntBigEndian | Encoding::IntLittleEndian => {
+            if !(1..=32).contains(&common.bits_per_sample) {
+                return Err(AudioError::Unsupported(format!(
+                    "{}-bit AIFF samples",
+                    common.bits_per_sample
+                )));
+            }
+            common.bits_per_sample.div_ceil(8) as usize
+        }
+    };
+
+    // The declared frame count wins, but never read past the end of the chunk.
+    let available = sound_data.len() / bytes_per_sample;
+    let count = (common.frames as usize * common.channels as usize).min(available);
+    let scaling_factor = 1.0 / (1_u64 << (bytes_per_sample * 8 - 1)) as f32;
+
+    let samples = sound_data
+        .chunks_exact(bytes_per_sample)
+        .take(count)
+        .map(|raw| match common.encoding {
+            Encoding::Float32 => f32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]),
+            Encoding::Float64 => f64::from_be_bytes(raw.try_into().unwrap()) as f32,
+            Encoding::IntBigEndian => sign_extend(raw.iter().copied()) as f32 * scaling_factor,
+            Encoding::IntLittleEndian => {
+                sign_extend(raw.iter().rev().copied()) as f32 * scaling_factor
+            }
+        })
+        .collect();
+
+    Ok(AudioFile {
+        sample_rate: common.sample_rate.round() as u32,
+        channels: common.channels,
+        samples,
+        metadata: SampleMetadata::default(),
+    })
+}
+
+/// Iterates over the `(id, body)` pairs of the chunks in a FORM container. A
+/// truncated chunk yields whatever data is left and ends the iteration.
+fn chunks(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+
+        let id = [data[0], data[1], data[2], data[3]];
+        let size = read_u32_be(data, 4) as usize;
+        let body = &data[8..8_usize.saturating_add(size).min(data.len())];
+
+        // Chunks are padded to an even number of bytes.
+        let padded = 8_usize
+            .saturating_add(size)
+            .saturating_add(size & 1)
+            .min(data.len());
+        data = &data[padded..];
+
+        Some((id, body))
+    })
+}
+
+fn parse_common(body: &[u8], is_aifc: bool) -> Result<Common, AudioError> {
+    if body.len() < 18 {
+        return Err(AudioError::Malformed("COMM chunk is too short"));
+    }
+
+    let encoding = if is_aifc {
+        match body.get(18..22) {
+            Some(b"NONE") | Some(b"twos") => Encoding::IntBigEndian,
+            Some(b"sowt") => Encoding::IntLittleEndian,
+            Some(b"fl32") | Some(b"FL32") => Encoding::Float32,
+            Some(b"fl64") | Some(b"FL64") => Encoding::Float64,
+            Some(other) => {
+                return Err(AudioError::Unsupported(format!(
+                    "AIFF-C compression type '{}'",
+                    String::from_utf8_lossy(other)
+                )))
+            }
+            None => {
+                return Err(AudioError::Malformed(
+                    "COMM chunk is missing a compression type",
+                ))
+            }
+        }
+    } else {
+        Encoding::IntBigEndian
+    };
+
+    Ok(Common {
+        channels: u16::from_be_bytes([body[0], body[1]]),
+        frames: read_u32_be(body, 2),
+        bits_per_sample: u16::from_be_bytes([body[6], body[7]]),
+        sample_rate: extended_to_f64(body[8..18].try_into().unwrap()),
+        encoding,
+    })
+}
+
+/// Converts an 80-bit IEEE 754 extended precision float, which AIFF uses to
+/// store the sample rate, into an f64.
+fn extended_to_f64(bytes: [u8; 10]) -> f64 {
+    let sign = if bytes[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let exponent = (u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7fff) as i32;
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+
+    // The mantissa has an explicit integer bit, so it represents 1.63 fixed point.
+    sign * mantissa as f64 * 2.0_f64.powi(exponent - 16383 - 63)
+}
+
+/// Reads a big-endian integer of up to four bytes and sign extends it to i32.
+fn sign_extend(bytes: impl Iterator<Item = u8>) -> i32 {
+    let mut value: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        value = (value << 8) | byte as u32;
+        bits += 8;
+    }
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a minimal AIFF file with 16-bit big-endian samples.
+    fn aiff_16bit(channels: u16, sample_rate: [u8; 10], samples: &[i16]) -> Vec<u8> {
+        let mut comm = Vec::new();
+        comm.extend_from_slice(&channels.to_be_bytes());
+        comm.extend_from_slice(&((samples.len() / channels as usize) as u32).to_be_bytes());
+        comm.extend_from_slice(&16_u16.to_be_bytes());
+        comm.extend_from_slice(&sample_rate);
+
+        let mut ssnd = vec![0; 8];
+        for sample in samples {
+            ssnd.extend_from_slice(&sample.to_be_bytes());
+        }
+
+        let mut body = b"AIFF".to_vec();
+        for (id, chunk) in [(b"COMM", comm), (b"SSND", ssnd)] {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            body.extend_from_slice(&chunk);
+        }
+
+        let mut file = b"FORM".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        file.extend_from_slice(&body);
+        file
+    }
+
+    // 44100.0 as an 80-bit extended float.
+    const RATE_44100: [u8; 10] = [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn extended_sample_rates() {
+        assert_eq!(extended_to_f64(RATE_44100), 44100.0);
+        assert_eq!(
+            extended_to_f64([0x40, 0x0e, 0xbb, 0x80, 0, 0, 0, 0, 0, 0]),
+            48000.0
+        );
+        assert_eq!(extended_to_f64([0; 10]), 0.0);
+    }
+
+    #[test]
+    fn decodes_16bit_stereo() {
+        let file = aiff_16bit(2, RATE_44100, &[0, i16::MIN, 16384, -16384]);
+        let decoded = decode(&file).unwrap();
+
+        assert_eq!(decoded.sample_rate, 44100);
+        assert_eq!(decoded.channels, 2);
+        assert_eq!(decoded.samples, vec![0.0, -1.0, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn truncated_sound_data_is_clamped() {
+        let mut file = aiff_16bit(1, RATE_44100, &[1000, 2000, 3000]);
+        file.truncate(file.len() - 2);
+        assert_eq!(decode(&file).unwrap().samples.len(), 2);
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::io::Cursor;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use claxon::FlacReader;
+use hound::{SampleFormat, WavReader};
+use lewton::inside_ogg::OggStreamReader;
+
+use crate::{aiff, wav};
+
+/// The audio container formats that can be decoded by [`load`].
+#[derive(Clone, Debug, Copy, Eq, PartialEq)]
+pub enum AudioFormat {
+    Wav,
+    Flac,
+    Aiff,
+    OggVorbis,
+}
+
+impl AudioFormat {
+    /// Detects the container format from the first bytes of a file.
+    ///
+    /// Returns `None` if the header doesn't match any supported format.
+    pub fn detect(header: &[u8]) -> Option<AudioFormat> {
+        match header {
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', b'F' | b'C', ..] => {
+                Some(Self::Aiff)
+            }
+            [b'O', b'g', b'g', b'S', ..] if starts_vorbis_stream(header) => Some(Self::OggVorbis),
+            _ => None,
+        }
+    }
+}
+
+/// Checks whether the first Ogg page carries a Vorbis identification header.
+/// The packet starts right after the 27 byte page header and the segment
+/// table, whose length is stored in the last byte of the page header.
+fn starts_vorbis_stream(page: &[u8]) -> bool {
+    let Some(&segments) = page.get(26) else {
+        return false;
+    };
+    let start = 27 + segments as usize;
+    page.get(start..start + 7) == Some(&b"\x01vorbis"[..])
+}
+
+/// Decoded audio data, independent of the container it was read from.
+#[derive(Clone, Debug, Default)]
+pub struct AudioFile {
+    /// The sample rate in Hz.
+    pub sample_rate: u32,
+    /// The number of interleaved channels in `samples`.
+    pub channels: u16,
+    /// Interleaved samples, normalized to the range `[-1.0, 1.0]`.
+    pub samples: Vec<f32>,
+    /// Sampler metadata embedded in the file, if the format supports it.
+    pub metadata: SampleMetadata,
+}
+
+/// Sampler metadata embedded in an audio file, such as the contents of the
+/// `smpl`, `inst` and `cue ` chunks of a WAV file.
+///
+/// All positions are in sample frames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SampleMetadata {
+    /// The MIDI note at which the sample plays back at its original pitch.
+    pub root_note: Option<u8>,
+    /// The pitch offset from `root_note` in cents.
+    pub fine_tune: f32,
+    /// The gain to apply to the sample in dB.
+    pub gain_db: f32,
+    /// The MIDI notes this sample is meant to be mapped to.
+    pub key_range: Option<RangeInclusive<u8>>,
+    /// The MIDI velocities this sample is meant to be mapped to.
+    pub velocity_range: Option<RangeInclusive<u8>>,
+    pub loops: Vec<SampleLoop>,
+    pub cue_points: Vec<CuePoint>,
+}
+
+/// The direction in which a loop is played.
+#[derive(Clone, Debug, Copy, Default, Eq, PartialEq)]
+pub enum LoopMode {
+    #[default]
+    Forward,
+    PingPong,
+    Backward,
+}
+
+/// A sustain loop. `end` is exclusive, so the loop covers `start..end`.
+#[derive(Clone, Debug, Copy, Default, Eq, PartialEq)]
+pub struct SampleLoop {
+    pub start: u32,
+    pub end: u32,
+    pub mode: LoopMode,
+    /// The number of times the loop is played, where 0 means infinitely.
+    pub play_count: u32,
+}
+
+/// A marker at a position in the sample.
+#[derive(Clone, Debug, Copy, Eq, PartialEq)]
+pub struct CuePoint {
+    pub id: u32,
+    pub position: u32,
+}
+
+impl AudioFile {
+    /// Returns the number of sample frames, i.e. samples per channel.
+    pub fn frames(&self) -> usize {
+        if self.channels == 0 {
+            0
+        } else {
+            self.samples.len() / self.channels as usize
+        }
+    }
+}
+
+/// An error that occurred while decoding an audio file.
+#[derive(Debug)]
+pub enum AudioError {
+    Io(std::io::Error),
+    /// The header doesn't match any of the supported formats.
+    UnknownFormat,
+    /// The file claims to be a supported format but is structurally invalid.
+    Malformed(&'static str),
+    /// The file is valid, but uses a feature we don't decode.
+    Unsupported(String),
+    Wav(hound::Error),
+    Flac(claxon::Error),
+    Vorbis(lewton::VorbisError),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioError::Io(e) => write!(f, "I/O error: {}", e),
+            AudioError::UnknownFormat => write!(f, "unrecognized audio file format"),
+            AudioError::Malformed(reason) => write!(f, "malformed audio file: {}", reason),
+            AudioError::Unsupported(what) => write!(f, "unsupported audio file: {}", what),
+            AudioError::Wav(e) => write!(f, "WAV error: {}", e),
+            AudioError::Flac(e) => write!(f, "FLAC error: {}", e),
+            AudioError::Vorbis(e) => write!(f, "Ogg Vorbis error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AudioError::Io(e) => Some(e),
+            AudioError::Wav(e) => Some(e),
+            AudioError::Flac(e) => Some(e),
+            AudioError::Vorbis(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for AudioError {
+    fn from(e: std::io::Error) -> Self {
+        AudioError::Io(e)
+    }
+}
+
+impl From<hound::Error> for AudioError {
+    fn from(e: hound::Error) -> Self {
+        AudioError::Wav(e)
+    }
+}
+
+impl From<claxon::Error> for AudioError {
+    fn from(e: claxon::Error) -> Self {
+        AudioError::Flac(e)
+    }
+}
+
+impl From<lewton::VorbisError> for AudioError {
+    fn from(e: lewton::VorbisError) -> Self {
+        AudioError::Vorbis(e)
+    }
+}
+
+/// A trait for types that can be decoded into an [`AudioFile`].
+pub trait Decodable {
+    /// Detects the container format and decodes the audio data.
+    fn decode(self) -> Result<AudioFile, AudioError>;
+}
+
+impl Decodable for PathBuf {
+    /// Reads and decodes an audio file from disk.
+    fn decode(self) -> Result<AudioFile, AudioError> {
+        std::fs::read(self)?.as_slice().decode()
+    }
+}
+
+impl Decodable for &[u8] {
+    /// Decodes an audio file that has already been read into memory.
+    fn decode(self) -> Result<AudioFile, AudioError> {
+        match AudioFormat::detect(self).ok_or(AudioError::UnknownFormat)? {
+            AudioFormat::Wav => decode_wav(self),
+            AudioFormat::Flac => decode_flac(self),
+            AudioFormat::Aiff => aiff::decode(self),
+            AudioFormat::OggVorbis => decode_vorbis(self),
+        }
+    }
+}
+
+/// Loads an audio file in any of the supported formats.
+///
+/// # Arguments
+///
+/// * `input` - An input that implements the `Decodable` trait.
+///
+/// # Returns
+///
+/// The decoded audio data, or an error describing why it could not be read.
+pub fn load<T: Decodable>(input: T) -> Result<AudioFile, AudioError> {
+    input.decode()
+}
+
+/// Returns the factor that maps an integer sample of the given bit depth to
+/// the range `[-1.0, 1.0]`.
+fn int_scaling_factor(bits_per_sample: u32) -> f32 {
+    1.0 / (1_u64 << (bits_per_sample - 1)) as f32
+}
+
+fn decode_wav(bytes: &[u8]) -> Result<AudioFile, AudioError> {
+    let mut reader = WavReader::new(Cursor::new(bytes))?;
+    let spec = reader.spec();
+
+    let samples = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        SampleFormat::Int => {
+            let scaling_factor = int_scaling_factor(spec.bits_per_sample as u32);
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scaling_factor))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    Ok(AudioFile {
+        sample_rate: spec.sample_rate,
+        channels: spec.channels,
+        samples,
+        metadata: wav::read_metadata(bytes),
+    })
+}
+
+fn decode_flac(bytes: &[u8]) -> Result<AudioFile, AudioError> {
+    let mut reader = FlacReader::new(Cursor::new(bytes))?;
+    let info = reader.streaminfo();
+    let scaling_factor = int_scaling_factor(info.bits_per_sample);
+
+    let samples = reader
+        .samples()
+        .map(|s| s.map(|s| s as f32 * scaling_factor))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(AudioFile {
+        sample_rate: info.sample_rate,
+        channels: info.channels as u16,
+        samples,
+        metadata: SampleMetadata::default(),
+    })
+}
+
+fn decode_vorbis(bytes: &[u8]) -> Result<AudioFile, AudioError> {
+    let mut reader = OggStreamReader::new(Cursor::new(bytes))?;
+    let scaling_factor = int_scaling_factor(16);
+
+    let mut samples = Vec::new();
+    while let Some(packet) = reader.read_dec_packet_itl()? {
+        samples.extend(packet.into_iter().map(|s| s as f32 * scaling_factor));
+    }
+
+    Ok(AudioFile {
+        sample_rate: reader.ident_hdr.audio_sample_rate,
+        channels: reader.ident_hdr.audio_channels as u16,
+        samples,
+        metadata: SampleMetadata::default(),
+    })
+}
@@ -1,3 +1,5 @@
+mod aiff;
+pub mod audio;
 pub mod resampler;
 pub mod wav;
 
@@ -1,48 +1,599 @@
-use dasp::{interpolate::linear::Linear, signal, Signal};
-
-/// Resamples the given audio data from one sample rate to another.
-///
-/// # Arguments
-///
-/// * `data` - A slice of f32 samples representing the audio data.
-/// * `sample_rate0` - The original sample rate of the audio data.
-/// * `sample_rate` - The target sample rate to resample the audio data to.
-///
-/// # Returns
-///
-/// A vector of f32 samples representing the resampled audio data.
-///
-/// # Panics
-///
-/// This function will panic if the input data length is not a multiple of 2.
-pub fn resample(data: &[f32], sample_rate0: f32, sample_rate: f32) -> Vec<f32> {
-    let n = data.len() / (2_usize);
-    let n = n * sample_rate as usize / sample_rate0 as usize;
-    let mut source = signal::from_interleaved_samples_iter::<_, [_; 2]>(data.iter().cloned());
-    let a = source.next();
-    let b = source.next();
-    let interp = Linear::new(a, b);
-    let mut data = Vec::with_capacity(n << 1);
-    for x in source
-        .from_hz_to_hz(interp, sample_rate0 as _, sample_rate as _)
-        .take(n)
-    {
-        data.push(x[0]);
-        data.push(x[1]);
-    }
-    data
-}
-
-/// Calculates the frequency in Hertz after applying a pitch shift.
-///
-/// # Arguments
-///
-/// * `hz` - The original frequency in Hertz.
-/// * `difference` - The pitch shift in semitones.
-///
-/// # Returns
-///
-/// The frequency in Hertz after applying the pitch shift.
-pub fn calc_hertz(hz: f32, difference: i32) -> f32 {
-    hz * f32::powf(2.0, (difference as f32) / 12.0)
-}
+use std::f64::consts::PI;
+
+/// The quality of the band-limiting filter used when resampling.
+///
+/// Higher qualities use longer windowed-sinc filters, which give a wider flat
+/// passband and stronger alias rejection at the cost of more CPU time.
+#[derive(Clone, Debug, Copy, Default, Eq, PartialEq)]
+pub enum Quality {
+    /// 60 dB alias rejection, flat up to about 77% of the Nyquist frequency.
+    Low,
+    /// 80 dB alias rejection, flat up to about 84% of the Nyquist frequency.
+    Medium,
+    /// 100 dB alias rejection, flat up to about 90% of the Nyquist frequency.
+    #[default]
+    High,
+    /// 120 dB alias rejection, flat up to about 94% of the Nyquist frequency.
+    Best,
+}
+
+impl Quality {
+    /// The number of zero crossings of the sinc on each side of the kernel.
+    fn zero_crossings(self) -> usize {
+        match self {
+            Quality::Low => 16,
+            Quality::Medium => 32,
+            Quality::High => 64,
+            Quality::Best => 128,
+        }
+    }
+
+    /// The stopband attenuation in dB, which determines the Kaiser window.
+    fn attenuation(self) -> f64 {
+        match self {
+            Quality::Low => 60.0,
+            Quality::Medium => 80.0,
+            Quality::High => 100.0,
+            Quality::Best => 120.0,
+        }
+    }
+
+    /// The number of table entries per zero crossing. The error of linearly
+    /// interpolating the table has to stay below the stopband attenuation.
+    fn table_resolution(self) -> usize {
+        match self {
+            Quality::Low => 512,
+            Quality::Medium => 1024,
+            Quality::High => 2048,
+            Quality::Best => 4096,
+        }
+    }
+}
+
+/// A Kaiser-windowed sinc low-pass filter, tabulated so it can be evaluated at
+/// arbitrary fractional offsets.
+///
+/// The cutoff is placed so the stopband starts exactly at the Nyquist
+/// frequency, which means nothing above it can alias back into the output.
+#[derive(Clone, Debug)]
+pub(crate) struct SincKernel {
+    /// One side of the symmetric kernel, from offset 0 to `zero_crossings`.
+    table: Vec<f32>,
+    zero_crossings: usize,
+    table_resolution: usize,
+}
+
+impl SincKernel {
+    pub(crate) fn new(quality: Quality) -> Self {
+        let zero_crossings = quality.zero_crossings();
+        let table_resolution = quality.table_resolution();
+        let attenuation = quality.attenuation();
+
+        // Kaiser's design formulas for the window shape and the resulting
+        // transition width, relative to the Nyquist frequency.
+        let beta = 0.1102 * (attenuation - 8.7);
+        let transition = (attenuation - 7.95) / (14.36 * zero_crossings as f64);
+        let cutoff = 1.0 - transition / 2.0;
+
+        let len = zero_crossings * table_resolution;
+        let mut table: Vec<f32> = (0..=len)
+            .map(|i| {
+                let x = i as f64 / table_resolution as f64;
+                let window = bessel_i0(beta * (1.0 - (x / zero_crossings as f64).powi(2)).sqrt())
+                    / bessel_i0(beta);
+                (cutoff * sinc(cutoff * x) * window) as f32
+            })
+            .collect();
+        // Guard entry so interpolating right before the end stays in bounds.
+        table.push(0.0);
+
+        Self {
+            table,
+            zero_crossings,
+            table_resolution,
+        }
+    }
+
+    /// The distance from the center at which the kernel becomes zero, in
+    /// samples at the filter's cutoff.
+    pub(crate) fn half_width(&self) -> f64 {
+        self.zero_crossings as f64
+    }
+
+    /// Evaluates the kernel at an offset from its center.
+    pub(crate) fn at(&self, x: f64) -> f32 {
+        let position = x.abs() * self.table_resolution as f64;
+        let index = position as usize;
+        if index >= self.table.len() - 1 {
+            return 0.0;
+        }
+
+        let fraction = (position - index as f64) as f32;
+        self.table[index] + (self.table[index + 1] - self.table[index]) * fraction
+    }
+}
+
+/// `sin(pi * x) / (pi * x)`
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The zeroth order modified Bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+    for k in 1..64 {
+        term *= (half_x / k as f64).powi(2);
+        sum += term;
+        if term < sum * 1e-16 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Returns the number of frames that `frames` input frames resample to.
+///
+/// The length is rounded to the nearest frame, so it is accurate for any
+/// ratio and not just integer sample rates.
+pub fn output_len(frames: usize, sample_rate0: f32, sample_rate: f32) -> usize {
+    (frames as f64 * sample_rate as f64 / sample_rate0 as f64).round() as usize
+}
+
+/// Resamples mono audio data from one sample rate to another.
+///
+/// This is [`resample_interleaved`] with a single channel and the default
+/// [`Quality`].
+///
+/// # Arguments
+///
+/// * `data` - A slice of f32 samples representing the audio data.
+/// * `sample_rate0` - The original sample rate of the audio data.
+/// * `sample_rate` - The target sample rate to resample the audio data to.
+///
+/// # Returns
+///
+/// A vector of f32 samples representing the resampled audio data.
+pub fn resample(data: &[f32], sample_rate0: f32, sample_rate: f32) -> Vec<f32> {
+    resample_interleaved(data, 1, sample_rate0, sample_rate, Quality::default())
+}
+
+/// Resamples interleaved audio data from one sample rate to another using a
+/// band-limited windowed-sinc filter.
+///
+/// When downsampling, the filter's cutoff is lowered to the new Nyquist
+/// frequency so content that no longer fits is removed instead of aliasing.
+///
+/// # Arguments
+///
+/// * `data` - Interleaved f32 samples. A trailing partial frame is ignored.
+/// * `channels` - The number of interleaved channels in `data`.
+/// * `sample_rate0` - The original sample rate of the audio data.
+/// * `sample_rate` - The target sample rate. Any positive ratio is supported.
+/// * `quality` - The quality of the band-limiting filter.
+///
+/// # Returns
+///
+/// Interleaved samples with [`output_len`] frames per channel.
+///
+/// # Panics
+///
+/// This function will panic if `channels` is zero.
+pub fn resample_interleaved(
+    data: &[f32],
+    channels: usize,
+    sample_rate0: f32,
+    sample_rate: f32,
+    quality: Quality,
+) -> Vec<f32> {
+    assert!(channels > 0, "cannot resample audio without channels");
+
+    let frames = data.len() / channels;
+    if sample_rate0 == sample_rate {
+        return data[..frames * channels].to_vec();
+    }
+
+    let kernel = SincKernel::new(quality);
+    let step = sample_rate0 as f64 / sample_rate as f64;
+    // Stretch the kernel when downsampling so its cutoff follows the new
+    // Nyquist frequency, and compensate for the added gain.
+    let scale = (1.0 / step).min(1.0);
+    let reach = kernel.half_width() / scale;
+
+    let out_frames = output_len(frames, sample_rate0, sample_rate);
+    let mut output = vec![0.0; out_frames * channels];
+
+    for (out_frame, out) in output.chunks_exact_mut(channels).enumerate() {
+        let center = out_frame as f64 * step;
+        let first = (center - reach).ceil().max(0.0) as usize;
+        let last = ((center + reach).floor() as usize).min(frames.saturating_sub(1));
+
+        for in_frame in first..=last {
+            let weight = kernel.at((center - in_frame as f64) * scale) * scale as f32;
+            let input = &data[in_frame * channels..(in_frame + 1) * channels];
+            for (out, input) in out.iter_mut().zip(input) {
+                *out += input * weight;
+            }
+        }
+    }
+
+    output
+}
+
+/// A stateful resampler that converts a stream of interleaved audio block by
+/// block, for use on the audio thread.
+///
+/// All memory is allocated in [`Resampler::new`], so [`Resampler::process`]
+/// and [`Resampler::set_ratio`] never allocate. The ratio can be changed
+/// between blocks, e.g. to follow a pitch modulation.
+#[derive(Clone, Debug)]
+pub struct Resampler {
+    kernel: SincKernel,
+    channels: usize,
+    /// The number of input frames advanced per output frame.
+    step: f64,
+    max_step: f64,
+    /// Interleaved input frames that are still within reach of the filter.
+    buffer: Vec<f32>,
+    /// The number of valid frames in `buffer`.
+    len: usize,
+    /// The position of the next output frame in `buffer`, in frames.
+    position: f64,
+}
+
+impl Resampler {
+    /// Creates a resampler with a ratio of 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The number of interleaved channels.
+    /// * `quality` - The quality of the band-limiting filter.
+    /// * `max_ratio` - The highest `sample_rate0 / sample_rate` ratio that will
+    ///   be passed to [`Resampler::set_ratio`]. Downsampling needs a longer
+    ///   filter, so this determines the size of the buffer.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `channels` is zero.
+    pub fn new(channels: usize, quality: Quality, max_ratio: f32) -> Self {
+        assert!(channels > 0, "cannot resample audio without channels");
+
+        let kernel = SincKernel::new(quality);
+        let max_step = (max_ratio as f64).max(1.0);
+        let window = (kernel.half_width() * max_step).ceil() as usize + 1;
+        // Room for the filter's reach on both sides of the output position,
+        // plus some slack so the buffer isn't shifted for every input frame.
+        let capacity = 2 * window + 256;
+
+        let mut resampler = Self {
+            kernel,
+            channels,
+            step: 1.0,
+            max_step,
+            buffer: vec![0.0; capacity * channels],
+            len: 0,
+            position: 0.0,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    /// Sets the conversion ratio. Takes effect from the next output frame.
+    ///
+    /// Ratios above the `max_ratio` given to [`Resampler::new`] are clamped.
+    pub fn set_ratio(&mut self, sample_rate0: f32, sample_rate: f32) {
+        self.step = (sample_rate0 as f64 / sample_rate as f64).min(self.max_step);
+    }
+
+    /// The number of input frames that have to be processed before the
+    /// first output frame is produced.
+    pub fn latency(&self) -> usize {
+        (self.kernel.half_width() / self.scale()).floor() as usize + 1
+    }
+
+    /// Clears the buffered input, as if the resampler was just created.
+    pub fn reset(&mut self) {
+        // Start with silence in front of the stream, which lines the output up
+        // with `resample_interleaved`.
+        let padding = (self.kernel.half_width() * self.max_step).ceil() as usize;
+        self.buffer.fill(0.0);
+        self.len = padding;
+        self.position = padding as f64;
+    }
+
+    /// Resamples as much of `input` into `output` as possible.
+    ///
+    /// Both slices are interleaved, and trailing partial frames are ignored.
+    /// Input that isn't consumed because `output` is full has to be passed
+    /// again in the next call.
+    ///
+    /// # Returns
+    ///
+    /// The number of input frames consumed and output frames produced.
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) -> (usize, usize) {
+        let channels = self.channels;
+        let in_frames = input.len() / channels;
+        let out_frames = output.len() / channels;
+        let scale = self.scale();
+        let reach = self.kernel.half_width() / scale;
+
+        let mut consumed = 0;
+        let mut produced = 0;
+        while produced < out_frames {
+            if self.position + reach < self.len as f64 {
+                let out = &mut output[produced * channels..(produced + 1) * channels];
+                out.fill(0.0);
+
+                let first = (self.position - reach).ceil().max(0.0) as usize;
+                let last = (self.position + reach).floor() as usize;
+                for frame in first..=last {
+                    let weight =
+                        self.kernel.at((self.position - frame as f64) * scale) * scale as f32;
+                    let input = &self.buffer[frame * channels..(frame + 1) * channels];
+                    for (out, input) in out.iter_mut().zip(input) {
+                        *out += input * weight;
+                    }
+                }
+
+                self.position += self.step;
+                produced += 1;
+            } else if consumed < in_frames {
+                if self.len * channels == self.buffer.len() {
+                    self.discard(reach);
+                }
+
+                let count = (self.buffer.len() / channels - self.len).min(in_frames - consumed);
+                self.buffer[self.len * channels..(self.len + count) * channels]
+                    .copy_from_slice(&input[consumed * channels..(consumed + count) * channels]);
+                self.len += count;
+                consumed += count;
+            } else {
+                break;
+            }
+        }
+
+        (consumed, produced)
+    }
+
+    /// How much the kernel is stretched to lower its cutoff when downsampling.
+    fn scale(&self) -> f64 {
+        (1.0 / self.step).min(1.0)
+    }
+
+    /// Drops the frames that the filter can no longer reach from the front of
+    /// the buffer.
+    fn discard(&mut self, reach: f64) {
+        let count = ((self.position - reach).floor().max(0.0) as usize).min(self.len);
+        self.buffer
+            .copy_within(count * self.channels..self.len * self.channels, 0);
+        self.len -= count;
+        self.position -= count as f64;
+    }
+}
+
+/// Calculates the frequency in Hertz after applying a pitch shift.
+///
+/// # Arguments
+///
+/// * `hz` - The original frequency in Hertz.
+/// * `difference` - The pitch shift in semitones.
+///
+/// # Returns
+///
+/// The frequency in Hertz after applying the pitch shift.
+pub fn calc_hertz(hz: f32, difference: i32) -> f32 {
+    hz * f32::powf(2.0, (difference as f32) / 12.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [Quality; 4] = [Quality::Low, Quality::Medium, Quality::High, Quality::Best];
+
+    /// Computed in f64, since f32 phase errors at large sample indices would
+    /// show up as broadband noise.
+    fn sine(frequency: f32, sample_rate: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * PI * frequency as f64 * i as f64 / sample_rate as f64).sin() as f32)
+            .collect()
+    }
+
+    /// The RMS level in dB relative to a full scale sine, ignoring the edges
+    /// where the filter runs into the start and end of the signal.
+    fn level_db(data: &[f32]) -> f32 {
+        let middle = &data[data.len() / 4..data.len() * 3 / 4];
+        let mean_square = middle.iter().map(|s| s * s).sum::<f32>() / middle.len() as f32;
+        10.0 * (mean_square * 2.0).log10()
+    }
+
+    /// The amplitude in dB of the sine with the given frequency in `data`,
+    /// found with a least squares fit so it doesn't depend on the number of
+    /// periods in the measured range.
+    fn sine_level_db(data: &[f32], frequency: f32, sample_rate: f32) -> f32 {
+        let (mut ss, mut sc, mut cc, mut xs, mut xc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, &x) in data
+            .iter()
+            .enumerate()
+            .skip(data.len() / 4)
+            .take(data.len() / 2)
+        {
+            let phase = 2.0 * PI * frequency as f64 * i as f64 / sample_rate as f64;
+            let (sin, cos) = phase.sin_cos();
+            let x = x as f64;
+            ss += sin * sin;
+            sc += sin * cos;
+            cc += cos * cos;
+            xs += x * sin;
+            xc += x * cos;
+        }
+
+        let determinant = ss * cc - sc * sc;
+        let a = (xs * cc - xc * sc) / determinant;
+        let b = (xc * ss - xs * sc) / determinant;
+        (10.0 * (a * a + b * b).log10()) as f32
+    }
+
+    #[test]
+    fn output_length_is_accurate() {
+        assert_eq!(output_len(44100, 44100.0, 48000.0), 48000);
+        assert_eq!(output_len(44100, 44100.0, 96000.0), 96000);
+        assert_eq!(output_len(48000, 48000.0, 44100.0), 44100);
+        assert_eq!(output_len(1000, 44100.0, calc_hertz(44100.0, -7)), 667);
+
+        let data = vec![0.0; 2 * 44100];
+        assert_eq!(
+            resample_interleaved(&data, 2, 44100.0, 48000.0, Quality::Low).len(),
+            2 * 48000
+        );
+        assert_eq!(resample(&data[..12345], 44100.0, 48000.0).len(), 13437);
+    }
+
+    #[test]
+    fn passband_is_flat() {
+        for quality in QUALITIES {
+            for frequency in [100.0, 1000.0, 5000.0, 12000.0] {
+                let input = sine(frequency, 44100.0, 8192);
+                for sample_rate in [48000.0, 96000.0, 32000.0] {
+                    let output = resample_interleaved(&input, 1, 44100.0, sample_rate, quality);
+                    let level = sine_level_db(&output, frequency, sample_rate);
+                    assert!(
+                        level.abs() < 0.05,
+                        "{:?} at {} Hz to {} Hz: {} dB",
+                        quality,
+                        frequency,
+                        sample_rate,
+                        level
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wide_passband_at_high_quality() {
+        for (quality, frequency) in [(Quality::High, 19000.0), (Quality::Best, 20000.0)] {
+            let output = resample(&sine(frequency, 44100.0, 8192), 44100.0, 48000.0);
+            let output = resample_interleaved(&output, 1, 48000.0, 96000.0, quality);
+            let level = sine_level_db(&output, frequency, 96000.0);
+            assert!(level.abs() < 0.1, "{:?}: {} dB", quality, level);
+        }
+    }
+
+    #[test]
+    fn rejects_aliases_when_downsampling() {
+        for quality in QUALITIES {
+            // These are above the new Nyquist frequency and would fold back to
+            // 14.1 kHz and 2.1 kHz.
+            for frequency in [30000.0, 42000.0] {
+                let input = sine(frequency, 96000.0, 16384);
+                let output = resample_interleaved(&input, 1, 96000.0, 44100.0, quality);
+                let level = level_db(&output);
+                assert!(
+                    level < -quality.attenuation() as f32 + 10.0,
+                    "{:?} at {} Hz: {} dB",
+                    quality,
+                    frequency,
+                    level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_images_when_upsampling() {
+        // Upsampling a 15 kHz sine must not leave a mirror image at 29.1 kHz,
+        // so whatever isn't the original sine should be far below it.
+        let input = sine(15000.0, 44100.0, 8192);
+        let output = resample_interleaved(&input, 1, 44100.0, 96000.0, Quality::High);
+        let reference = sine(15000.0, 96000.0, output.len());
+        let residual: Vec<f32> = output.iter().zip(&reference).map(|(a, b)| a - b).collect();
+        assert!(level_db(&residual) < -90.0, "{} dB", level_db(&residual));
+    }
+
+    #[test]
+    fn channels_are_independent() {
+        let left = sine(440.0, 44100.0, 4096);
+        let interleaved: Vec<f32> = left.iter().flat_map(|&s| [s, 0.0]).collect();
+        let output = resample_interleaved(&interleaved, 2, 44100.0, 48000.0, Quality::Medium);
+        let mono = resample_interleaved(&left, 1, 44100.0, 48000.0, Quality::Medium);
+
+        assert!(output.iter().skip(1).step_by(2).all(|&s| s == 0.0));
+        assert!(output.iter().step_by(2).zip(&mono).all(|(a, b)| a == b));
+    }
+
+    #[test]
+    fn streaming_matches_offline() {
+        let left = sine(440.0, 44100.0, 4096);
+        let right = sine(3000.0, 44100.0, 4096);
+        let input: Vec<f32> = left
+            .iter()
+            .zip(&right)
+            .flat_map(|(&l, &r)| [l, r])
+            .collect();
+        let offline = resample_interleaved(&input, 2, 44100.0, 48000.0, Quality::Medium);
+
+        let mut resampler = Resampler::new(2, Quality::Medium, 1.0);
+        resampler.set_ratio(44100.0, 48000.0);
+
+        // Feed uneven blocks into a small output buffer.
+        let mut streamed = Vec::new();
+        let mut output = [0.0; 2 * 37];
+        let mut remaining = &input[..];
+        for block in [1, 7, 64, 3, 500].iter().cycle() {
+            if remaining.is_empty() {
+                break;
+            }
+            let mut block = &remaining[..(2 * block).min(remaining.len())];
+            remaining = &remaining[block.len()..];
+            while !block.is_empty() {
+                let (consumed, produced) = resampler.process(block, &mut output);
+                streamed.extend_from_slice(&output[..2 * produced]);
+                block = &block[2 * consumed..];
+            }
+        }
+        loop {
+            match resampler.process(&[], &mut output) {
+                (_, 0) => break,
+                (_, produced) => streamed.extend_from_slice(&output[..2 * produced]),
+            }
+        }
+
+        // The tail is still waiting for input that would follow it.
+        let expected = output_len(4096 - resampler.latency(), 44100.0, 48000.0);
+        assert!(streamed.len() >= 2 * expected);
+
+        // The streaming position is accumulated instead of multiplied, so a tap
+        // at the very edge of the kernel can be rounded to the other side.
+        for (a, b) in streamed.iter().zip(&offline) {
+            assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn follows_ratio_changes() {
+        let input = sine(1000.0, 44100.0, 44100);
+        let mut resampler = Resampler::new(1, Quality::Low, 2.0);
+        let mut output = [0.0; 128];
+        let mut produced_frames = 0;
+
+        for (i, block) in input.chunks(32).enumerate() {
+            // Sweep between an octave up and down and beyond the limit.
+            let ratio = 2.0_f32.powf((i as f32 / 100.0).sin() * 1.5);
+            resampler.set_ratio(44100.0 * ratio, 44100.0);
+
+            let (consumed, produced) = resampler.process(block, &mut output);
+            assert_eq!(consumed, block.len());
+            assert!(output[..produced].iter().all(|s| s.abs() < 1.1));
+            produced_frames += produced;
+        }
+
+        assert!(produced_frames > 0);
+        assert!(resampler.step <= 2.0);
+    }
+}
@@ -1,8 +1,10 @@
-use std::io::Cursor;
-use std::path::PathBuf;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
 
 use hound::{SampleFormat, WavReader};
 
+use crate::audio::{AudioFile, CuePoint, LoopMode, SampleLoop, SampleMetadata};
+
 /// A trait for types that can be loaded into a vector of f32 samples.
 pub trait Loadable {
     /// Loads the data and returns a vector of f32 samples.
@@ -83,3 +85,530 @@ fn read_samples<R: std::io::Read>(mut reader: WavReader<R>) -> Vec<f32> {
 pub fn load<T: Loadable>(input: T) -> Vec<f32> {
     input.load()
 }
+
+/// Reads the sampler metadata from the `smpl`, `inst` and `cue ` chunks of a
+/// WAV file.
+///
+/// When both `smpl` and `inst` specify a root note and fine tune, the values
+/// from `smpl` are used. Missing, truncated or otherwise malformed chunks are
+/// skipped, so this never fails.
+///
+/// # Arguments
+///
+/// * `bytes` - The complete contents of a WAV file.
+pub fn read_metadata(bytes: &[u8]) -> SampleMetadata {
+    let mut metadata = SampleMetadata::default();
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return metadata;
+    }
+
+    let mut smpl = None;
+    let mut inst = None;
+    let mut cue = None;
+    for (id, body) in riff_chunks(&bytes[12..]) {
+        match &id {
+            b"smpl" if body.len() >= 36 => smpl = Some(body),
+            b"inst" if body.len() >= 7 => inst = Some(body),
+            b"cue " if body.len() >= 4 => cue = Some(body),
+            _ => (),
+        }
+    }
+
+    if let Some(body) = inst {
+        metadata.root_note = Some(body[0].min(127));
+        metadata.fine_tune = body[1] as i8 as f32;
+        metadata.gain_db = body[2] as i8 as f32;
+        metadata.key_range = Some(body[3].min(127)..=body[4].min(127));
+        metadata.velocity_range = Some(body[5].min(127)..=body[6].min(127));
+    }
+
+    if let Some(body) = smpl {
+        metadata.root_note = Some(read_u32_le(body, 12).min(127) as u8);
+        // The pitch fraction is a fraction of a semitone above the root note.
+        metadata.fine_tune = read_u32_le(body, 16) as f32 / 4_294_967_296.0 * 100.0;
+        // Report the nearest note as the root, so -25 cents doesn't turn into the
+        // note below at +75 cents.
+        if metadata.fine_tune > 50.0 && metadata.root_note < Some(127) {
+            metadata.root_note = metadata.root_note.map(|note| note + 1);
+            metadata.fine_tune -= 100.0;
+        }
+
+        let loop_count = read_u32_le(body, 28) as usize;
+        metadata.loops = body[36..]
+            .chunks_exact(24)
+            .take(loop_count)
+            .map(|sample_loop| SampleLoop {
+                start: read_u32_le(sample_loop, 8),
+                // The end point in the chunk is inclusive.
+                end: read_u32_le(sample_loop, 12).saturating_add(1),
+                mode: match read_u32_le(sample_loop, 4) {
+                    1 => LoopMode::PingPong,
+                    2 => LoopMode::Backward,
+                    _ => LoopMode::Forward,
+                },
+                play_count: read_u32_le(sample_loop, 20),
+            })
+            .collect();
+    }
+
+    if let Some(body) = cue {
+        let cue_count = read_u32_le(body, 0) as usize;
+        metadata.cue_points = body[4..]
+            .chunks_exact(24)
+            .take(cue_count)
+            .map(|cue_point| CuePoint {
+                id: read_u32_le(cue_point, 0),
+                position: read_u32_le(cue_point, 20),
+            })
+            .collect();
+    }
+
+    metadata
+}
+
+/// The sample format used when writing a WAV file.
+#[derive(Clone, Debug, Copy, Default, Eq, PartialEq)]
+pub enum BitDepth {
+    Int16,
+    #[default]
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl BitDepth {
+    fn bits(self) -> u16 {
+        match self {
+            BitDepth::Int16 => 16,
+            BitDepth::Int24 => 24,
+            BitDepth::Int32 | BitDepth::Float32 => 32,
+        }
+    }
+}
+
+/// Options for [`write`] and [`save`].
+#[derive(Clone, Debug, Copy, Default, Eq, PartialEq)]
+pub struct ExportOptions {
+    pub bit_depth: BitDepth,
+    /// Adds TPDF dither before quantizing to an integer format. Has no effect
+    /// when writing floats.
+    pub dither: bool,
+}
+
+/// Writes audio data to a WAV file on disk. See [`write`].
+pub fn save(
+    path: impl AsRef<Path>,
+    audio: &AudioFile,
+    options: ExportOptions,
+) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write(&mut file, audio, options)?;
+    file.flush()
+}
+
+/// Writes audio data as a WAV file.
+///
+/// The sampler metadata is written to `smpl`, `inst` and `cue ` chunks so it
+/// survives a round trip through [`crate::audio::load`]. The `smpl` chunk is
+/// only written when there is a root note or a loop, the `inst` chunk only
+/// when there is a key or velocity range.
+///
+/// # Arguments
+///
+/// * `writer` - The destination for the encoded file.
+/// * `audio` - The audio data and metadata to write.
+/// * `options` - The sample format and whether to dither.
+pub fn write<W: Write>(
+    writer: &mut W,
+    audio: &AudioFile,
+    options: ExportOptions,
+) -> std::io::Result<()> {
+    let channels = audio.channels.max(1);
+    let bits = options.bit_depth.bits();
+    let block_align = channels * bits / 8;
+
+    let mut fmt = Vec::with_capacity(18);
+    let format_tag: u16 = if options.bit_depth == BitDepth::Float32 {
+        3
+    } else {
+        1
+    };
+    fmt.extend_from_slice(&format_tag.to_le_bytes());
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&audio.sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(audio.sample_rate * block_align as u32).to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&bits.to_le_bytes());
+
+    let mut chunks: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+    if options.bit_depth == BitDepth::Float32 {
+        // Non-PCM formats carry an (empty) extension and a frame count.
+        fmt.extend_from_slice(&0_u16.to_le_bytes());
+        chunks.push((*b"fmt ", fmt));
+        chunks.push((
+            *b"fact",
+            (audio.samples.len() as u32 / channels as u32)
+                .to_le_bytes()
+                .to_vec(),
+        ));
+    } else {
+        chunks.push((*b"fmt ", fmt));
+    }
+
+    chunks.push((*b"data", encode_samples(&audio.samples, options)));
+
+    let metadata = &audio.metadata;
+    if metadata.root_note.is_some() || !metadata.loops.is_empty() {
+        chunks.push((*b"smpl", smpl_chunk(metadata, audio.sample_rate)));
+    }
+    if metadata.key_range.is_some() || metadata.velocity_range.is_some() {
+        chunks.push((*b"inst", inst_chunk(metadata)));
+    }
+    if !metadata.cue_points.is_empty() {
+        chunks.push((*b"cue ", cue_chunk(metadata)));
+    }
+
+    let riff_size: usize = 4 + chunks
+        .iter()
+        .map(|(_, body)| 8 + body.len() + (body.len() & 1))
+        .sum::<usize>();
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(riff_size as u32).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    for (id, body) in &chunks {
+        writer.write_all(id)?;
+        writer.write_all(&(body.len() as u32).to_le_bytes())?;
+        writer.write_all(body)?;
+        if body.len() & 1 == 1 {
+            writer.write_all(&[0])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts f32 samples to the little-endian bytes of the `data` chunk.
+fn encode_samples(samples: &[f32], options: ExportOptions) -> Vec<u8> {
+    let bytes_per_sample = options.bit_depth.bits() as usize / 8;
+    let mut data = Vec::with_capacity(samples.len() * bytes_per_sample);
+
+    if options.bit_depth == BitDepth::Float32 {
+        for sample in samples {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        return data;
+    }
+
+    let scale = (1_u64 << (options.bit_depth.bits() - 1)) as f64;
+    let mut dither = TpdfDither::default();
+    for &sample in samples {
+        let noise = if options.dither { dither.next() } else { 0.0 };
+        let quantized = (sample as f64 * scale + noise)
+            .round()
+            .clamp(-scale, scale - 1.0) as i32;
+        data.extend_from_slice(&quantized.to_le_bytes()[..bytes_per_sample]);
+    }
+
+    data
+}
+
+fn smpl_chunk(metadata: &SampleMetadata, sample_rate: u32) -> Vec<u8> {
+    // The pitch fraction can only raise the pitch, so negative fine tunings are
+    // expressed relative to the note below the root.
+    let mut root_note = metadata.root_note.unwrap_or(60) as u32;
+    let mut fine_tune = metadata.fine_tune.clamp(-99.0, 99.0);
+    if fine_tune < 0.0 && root_note > 0 {
+        root_note -= 1;
+        fine_tune += 100.0;
+    }
+    let pitch_fraction = (fine_tune.max(0.0) as f64 / 100.0 * 4_294_967_296.0) as u32;
+    let sample_period = 1_000_000_000 / sample_rate.max(1);
+
+    let mut words = vec![0, 0, sample_period, root_note, pitch_fraction, 0, 0];
+    words.push(metadata.loops.len() as u32);
+    words.push(0);
+    for (id, sample_loop) in metadata.loops.iter().enumerate() {
+        words.push(id as u32);
+        words.push(match sample_loop.mode {
+            LoopMode::Forward => 0,
+            LoopMode::PingPong => 1,
+            LoopMode::Backward => 2,
+        });
+        words.push(sample_loop.start);
+        words.push(sample_loop.end.saturating_sub(1));
+        words.push(0);
+        words.push(sample_loop.play_count);
+    }
+
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+fn inst_chunk(metadata: &SampleMetadata) -> Vec<u8> {
+    let key_range = metadata.key_range.clone().unwrap_or(0..=127);
+    let velocity_range = metadata.velocity_range.clone().unwrap_or(1..=127);
+
+    vec![
+        metadata.root_note.unwrap_or(60),
+        metadata.fine_tune.round().clamp(-50.0, 50.0) as i8 as u8,
+        metadata.gain_db.round().clamp(-64.0, 64.0) as i8 as u8,
+        *key_range.start(),
+        *key_range.end(),
+        *velocity_range.start(),
+        *velocity_range.end(),
+    ]
+}
+
+fn cue_chunk(metadata: &SampleMetadata) -> Vec<u8> {
+    let mut words = vec![metadata.cue_points.len() as u32];
+    for cue_point in &metadata.cue_points {
+        words.extend_from_slice(&[
+            cue_point.id,
+            cue_point.position,
+            u32::from_le_bytes(*b"data"),
+            0,
+            0,
+            cue_point.position,
+        ]);
+    }
+
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Generates triangular probability density dither with a peak amplitude of
+/// one LSB, using a xorshift generator so exports are reproducible.
+struct TpdfDither {
+    state: u32,
+}
+
+impl Default for TpdfDither {
+    fn default() -> Self {
+        Self { state: 0x9e37_79b9 }
+    }
+}
+
+impl TpdfDither {
+    /// Returns a uniformly distributed value in `[-0.5, 0.5)`.
+    fn next_uniform(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f64 / 4_294_967_296.0 - 0.5
+    }
+
+    fn next(&mut self) -> f64 {
+        self.next_uniform() + self.next_uniform()
+    }
+}
+
+/// Iterates over the `(id, body)` pairs of the chunks in a RIFF container. A
+/// truncated chunk yields whatever data is left and ends the iteration.
+fn riff_chunks(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+
+        let id = [data[0], data[1], data[2], data[3]];
+        let size = read_u32_le(data, 4) as usize;
+        let body = &data[8..8_usize.saturating_add(size).min(data.len())];
+
+        // Chunks are padded to an even number of bytes.
+        let padded = 8_usize
+            .saturating_add(size)
+            .saturating_add(size & 1)
+            .min(data.len());
+        data = &data[padded..];
+
+        Some((id, body))
+    })
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], words: &[u32]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(words.len() as u32 * 4).to_le_bytes());
+        for word in words {
+            chunk.extend_from_slice(&word.to_le_bytes());
+        }
+        chunk
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        file.extend_from_slice(&body);
+        file
+    }
+
+    #[test]
+    fn reads_smpl_and_cue_chunks() {
+        #[rustfmt::skip]
+        let smpl = chunk(b"smpl", &[
+            0, 0, 22675, 60, 0x8000_0000, 0, 0, 1, 0,
+            // Loop: id, type, start, end, fraction, play count
+            0, 1, 100, 199, 0, 0,
+        ]);
+        let cue = chunk(b"cue ", &[1, 7, 0, u32::from_le_bytes(*b"data"), 0, 0, 42]);
+
+        let metadata = read_metadata(&riff(&[smpl, cue]));
+        assert_eq!(metadata.root_note, Some(60));
+        assert_eq!(metadata.fine_tune, 50.0);
+        assert_eq!(
+            metadata.loops,
+            vec![SampleLoop {
+                start: 100,
+                end: 200,
+                mode: LoopMode::PingPong,
+                play_count: 0,
+            }]
+        );
+        assert_eq!(
+            metadata.cue_points,
+            vec![CuePoint {
+                id: 7,
+                position: 42
+            }]
+        );
+    }
+
+    #[test]
+    fn reads_inst_chunk() {
+        let mut inst = b"inst".to_vec();
+        inst.extend_from_slice(&7_u32.to_le_bytes());
+        inst.extend_from_slice(&[48, (-12_i8) as u8, 3, 36, 59, 1, 100, 0]);
+
+        let metadata = read_metadata(&riff(&[inst]));
+        assert_eq!(metadata.root_note, Some(48));
+        assert_eq!(metadata.fine_tune, -12.0);
+        assert_eq!(metadata.gain_db, 3.0);
+        assert_eq!(metadata.key_range, Some(36..=59));
+        assert_eq!(metadata.velocity_range, Some(1..=100));
+    }
+
+    #[test]
+    fn ignores_truncated_chunks() {
+        let mut file = riff(&[chunk(b"smpl", &[0; 12])]);
+        file.truncate(file.len() - 20);
+        assert_eq!(read_metadata(&file), SampleMetadata::default());
+    }
+
+    fn round_trip(audio: &AudioFile, options: ExportOptions) -> AudioFile {
+        let mut file = Vec::new();
+        write(&mut file, audio, options).unwrap();
+        crate::audio::load(file.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn write_round_trips_all_bit_depths() {
+        let audio = AudioFile {
+            sample_rate: 48000,
+            channels: 2,
+            samples: vec![0.0, 0.5, -0.5, 0.25, -1.0, 0.999],
+            ..Default::default()
+        };
+
+        for (bit_depth, tolerance) in [
+            (BitDepth::Int16, 1.0 / 32768.0),
+            (BitDepth::Int24, 1.0 / 8_388_608.0),
+            (BitDepth::Int32, 1e-7),
+            (BitDepth::Float32, 0.0),
+        ] {
+            let decoded = round_trip(
+                &audio,
+                ExportOptions {
+                    bit_depth,
+                    dither: false,
+                },
+            );
+            assert_eq!(decoded.sample_rate, 48000);
+            assert_eq!(decoded.channels, 2);
+            for (a, b) in audio.samples.iter().zip(&decoded.samples) {
+                assert!(
+                    (a - b).abs() <= tolerance,
+                    "{:?}: {} != {}",
+                    bit_depth,
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dither_stays_within_two_lsb() {
+        let audio = AudioFile {
+            sample_rate: 44100,
+            channels: 1,
+            samples: (0..1000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect(),
+            ..Default::default()
+        };
+
+        let options = ExportOptions {
+            bit_depth: BitDepth::Int16,
+            dither: true,
+        };
+        let decoded = round_trip(&audio, options);
+        let lsb = 1.0 / 32768.0;
+        assert!(audio
+            .samples
+            .iter()
+            .zip(&decoded.samples)
+            .all(|(a, b)| (a - b).abs() <= 2.0 * lsb));
+
+        // Dither moves every sample by at most 2 LSB from where plain
+        // rounding puts it.
+        let undithered = round_trip(
+            &audio,
+            ExportOptions {
+                dither: false,
+                ..options
+            },
+        );
+        assert!(decoded
+            .samples
+            .iter()
+            .zip(&undithered.samples)
+            .all(|(a, b)| (a - b).abs() <= 2.0 * lsb));
+    }
+
+    #[test]
+    fn write_round_trips_metadata() {
+        let metadata = SampleMetadata {
+            root_note: Some(57),
+            fine_tune: -25.0,
+            gain_db: -6.0,
+            key_range: Some(55..=59),
+            velocity_range: Some(1..=127),
+            loops: vec![SampleLoop {
+                start: 10,
+                end: 90,
+                mode: LoopMode::Forward,
+                play_count: 0,
+            }],
+            cue_points: vec![CuePoint { id: 1, position: 5 }],
+        };
+        let audio = AudioFile {
+            sample_rate: 44100,
+            channels: 1,
+            samples: vec![0.0; 101],
+            metadata: metadata.clone(),
+        };
+
+        let decoded = round_trip(&audio, ExportOptions::default());
+        assert_eq!(decoded.samples.len(), 101);
+        assert_eq!(decoded.metadata.root_note, Some(57));
+        assert!((decoded.metadata.fine_tune + 25.0).abs() < 1e-3);
+        assert_eq!(decoded.metadata.key_range, metadata.key_range);
+        assert_eq!(decoded.metadata.loops, metadata.loops);
+        assert_eq!(decoded.metadata.cue_points, metadata.cue_points);
+    }
+}
@@ -1,21 +1,29 @@
+/// Envelope times in seconds and the sample counts they work out to at a
+/// sample rate.
 #[derive(Clone, Debug)]
 pub struct Adsr {
+    attack_s: f32,
+    decay_s: f32,
+    sustain_level: f32,
+    release_s: f32,
+    sample_rate: f32,
     attack_samples: u32,
     decay_samples: u32,
-    sustain_level: f32,
     release_samples: u32,
-    sample_rate: f32,
 }
 
 impl Adsr {
     /// Creates a new ADSR configuration.
     pub fn new(sample_rate: f32) -> Self {
         Self {
+            attack_s: 0.0,
+            decay_s: 0.0,
+            sustain_level: 1.0,
+            release_s: 0.0,
+            sample_rate,
             attack_samples: 0,
             decay_samples: 0,
-            sustain_level: 1.0,
             release_samples: 0,
-            sample_rate,
         }
     }
 
@@ -27,10 +35,24 @@ impl Adsr {
         sustain_level: f32,
         release_s: f32,
     ) {
-        self.attack_samples = (attack_s * self.sample_rate).max(0.0) as u32;
-        self.decay_samples = (decay_s * self.sample_rate).max(0.0) as u32;
+        self.attack_s = attack_s.max(0.0);
+        self.decay_s = decay_s.max(0.0);
         self.sustain_level = sustain_level.clamp(0.0, 1.0);
-        self.release_samples = (release_s * self.sample_rate).max(0.0) as u32;
+        self.release_s = release_s.max(0.0);
+        self.update_samples();
+    }
+
+    /// Changes the sample rate, keeping the times in seconds.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_samples();
+    }
+
+    fn update_samples(&mut self) {
+        let samples = |seconds: f32| (seconds * self.sample_rate).round() as u32;
+        self.attack_samples = samples(self.attack_s);
+        self.decay_samples = samples(self.decay_s);
+        self.release_samples = samples(self.release_s);
     }
 }
 
@@ -136,3 +158,50 @@ impl Envelope {
         !matches!(self.phase, EnvelopePhase::Off)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATES: [f32; 4] = [44100.0, 48000.0, 88200.0, 96000.0];
+
+    /// Returns how many samples it takes until `done` returns `true`.
+    fn samples_until(envelope: &mut Envelope, done: impl Fn(&Envelope, f32) -> bool) -> usize {
+        (1..)
+            .find(|_| {
+                let value = envelope.next_value();
+                done(envelope, value)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn times_are_in_seconds_at_any_sample_rate() {
+        for sample_rate in SAMPLE_RATES {
+            let mut adsr = Adsr::new(sample_rate);
+            adsr.set_parameters(0.01, 0.1, 0.5, 0.2);
+            let mut envelope = Envelope::new(adsr);
+
+            let attack = samples_until(&mut envelope, |_, value| value >= 1.0);
+            assert_eq!(attack, (0.01 * sample_rate).round() as usize);
+            let decay = samples_until(&mut envelope, |_, value| value <= 0.5);
+            assert_eq!(decay, (0.1 * sample_rate).round() as usize);
+
+            envelope.note_off();
+            let release = samples_until(&mut envelope, |envelope, _| !envelope.is_active());
+            assert_eq!(release, (0.2 * sample_rate).round() as usize);
+        }
+    }
+
+    #[test]
+    fn changing_the_sample_rate_keeps_the_times() {
+        let mut adsr = Adsr::new(44100.0);
+        adsr.set_parameters(0.5, 0.25, 0.8, 2.0);
+        for sample_rate in SAMPLE_RATES {
+            adsr.set_sample_rate(sample_rate);
+            assert_eq!(adsr.attack_samples, (0.5 * sample_rate) as u32);
+            assert_eq!(adsr.decay_samples, (0.25 * sample_rate) as u32);
+            assert_eq!(adsr.release_samples, (2.0 * sample_rate) as u32);
+        }
+    }
+}
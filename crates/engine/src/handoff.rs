@@ -0,0 +1,89 @@
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// A single slot for passing boxed values to another thread without locks.
+///
+/// Neither [`Handoff::put`] nor [`Handoff::take`] allocate or free memory,
+/// so the audio thread can take values that were prepared on a background
+/// thread. Putting a value into a full slot replaces the value that wasn't
+/// taken yet.
+pub struct Handoff<T> {
+    slot: AtomicPtr<T>,
+    _owned: PhantomData<Box<T>>,
+}
+
+// SAFETY: Values are moved between threads, but never shared.
+unsafe impl<T: Send> Sync for Handoff<T> {}
+
+impl<T> Handoff<T> {
+    pub fn new() -> Self {
+        Self {
+            slot: AtomicPtr::new(ptr::null_mut()),
+            _owned: PhantomData,
+        }
+    }
+
+    /// Puts a value into the slot. Returns the previous value if it wasn't
+    /// taken yet, so the caller decides where it's dropped.
+    pub fn put(&self, value: Box<T>) -> Option<Box<T>> {
+        let previous = self.slot.swap(Box::into_raw(value), Ordering::AcqRel);
+        // SAFETY: Every pointer in the slot came from `Box::into_raw()`, and
+        // swapping it out transfers its ownership.
+        (!previous.is_null()).then(|| unsafe { Box::from_raw(previous) })
+    }
+
+    /// Takes the value out of the slot, if there is one.
+    pub fn take(&self) -> Option<Box<T>> {
+        let value = self.slot.swap(ptr::null_mut(), Ordering::AcqRel);
+        // SAFETY: See `put()`.
+        (!value.is_null()).then(|| unsafe { Box::from_raw(value) })
+    }
+}
+
+impl<T> Default for Handoff<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Handoff<T> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn passes_values_between_threads() {
+        let handoff = Arc::new(Handoff::new());
+        assert!(handoff.take().is_none());
+
+        let sender = Arc::clone(&handoff);
+        std::thread::spawn(move || {
+            assert!(sender.put(Box::new(vec![1.0_f32; 4])).is_none());
+            // The first value wasn't taken, so it's handed back.
+            assert_eq!(sender.put(Box::new(vec![2.0])).unwrap().len(), 4);
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(*handoff.take().unwrap(), [2.0]);
+        assert!(handoff.take().is_none());
+    }
+
+    #[test]
+    fn drops_values_that_were_not_taken() {
+        let value = Arc::new(());
+        let handoff = Handoff::new();
+        handoff.put(Box::new(Arc::clone(&value)));
+
+        drop(handoff);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
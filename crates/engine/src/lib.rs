@@ -1,4 +1,9 @@
 mod adsr;
+mod handoff;
+#[cfg(feature = "plugin")]
+pub mod library;
+mod slot;
 mod voice;
+mod voices;
 
-pub use self::{adsr::*, voice::*};
+pub use self::{adsr::*, handoff::*, slot::*, voice::*, voices::*};
@@ -0,0 +1,101 @@
+//! The parameter that picks an instrument from the user library, which the
+//! plugins play instead of their presets.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use instrument::library::{self, LibraryEntry};
+use instrument::{DecodeError, Instrument};
+use nih_plug::prelude::*;
+
+#[derive(Params)]
+pub struct LibraryParams {
+    /// Selects an instrument from the user library, which is played instead
+    /// of the preset. 0 means none.
+    #[id = "library"]
+    pub instrument: IntParam,
+    /// The instrument files that were found in the user library.
+    pub entries: Arc<Vec<LibraryEntry>>,
+    /// The selected user instrument. This is what gets loaded when the state
+    /// is restored, since the library may have changed in the meantime.
+    #[persist = "library-path"]
+    pub path: RwLock<Option<PathBuf>>,
+}
+
+impl LibraryParams {
+    /// Scans the user library of a plugin. `changed` is set whenever another
+    /// instrument is selected.
+    pub fn new(plugin: &str, changed: Arc<AtomicBool>) -> Self {
+        let entries = Arc::new(
+            library::user_library_dir(plugin)
+                .map(|dir| library::scan(&dir))
+                .unwrap_or_default(),
+        );
+
+        Self {
+            instrument: IntParam::new(
+                "Library",
+                0,
+                IntRange::Linear {
+                    min: 0,
+                    // The range can't be empty, even if the library is.
+                    max: entries.len().max(1) as i32,
+                },
+            )
+            .with_value_to_string({
+                let entries = entries.clone();
+                Arc::new(move |value| {
+                    match (value as usize).checked_sub(1).and_then(|i| entries.get(i)) {
+                        Some(entry) => entry.name.clone(),
+                        None => String::from("Off"),
+                    }
+                })
+            })
+            .with_callback(Arc::new(move |_| {
+                changed.store(true, Ordering::Relaxed);
+            })),
+            entries,
+            path: RwLock::new(None),
+        }
+    }
+
+    /// Remembers the instrument that is selected in the library.
+    pub fn select(&self) {
+        let index = self.instrument.value() as usize;
+        let entry = index.checked_sub(1).and_then(|i| self.entries.get(i));
+        *self.path.write().unwrap() = entry.map(|entry| entry.path.clone());
+    }
+
+    /// Loads the remembered user instrument with `load`. Returns `None` if
+    /// there is none or it can't be loaded, so the plugin falls back to its
+    /// preset. The voices only play mono samples, so other instruments are
+    /// mixed down.
+    pub fn load(
+        &self,
+        load: impl FnOnce(&Path) -> Result<Instrument, DecodeError>,
+    ) -> Option<Instrument> {
+        let path = self.path.read().unwrap().clone()?;
+        match load(&path) {
+            Ok(mut instrument) => {
+                if instrument.channels != 1 {
+                    nih_warn!(
+                        "{} has {} channels, mixing it down to mono",
+                        path.display(),
+                        instrument.channels
+                    );
+                    instrument.downmix();
+                }
+                Some(instrument)
+            }
+            Err(e) => {
+                nih_warn!(
+                    "Failed to load {}, falling back to the preset: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use instrument::Instrument;
+
+use crate::handoff::Handoff;
+use crate::voices::Voices;
+
+/// Work that is done on a background thread instead of the audio thread.
+pub enum Task<L> {
+    /// Loads an instrument. What to load is up to the plugin.
+    Load(L),
+    /// Drops an instrument that is no longer played.
+    Drop(Box<Instrument>),
+}
+
+impl<L> Task<L> {
+    /// Runs the task on the background thread. Instruments are loaded with
+    /// `load` and handed to the audio thread through `loaded`.
+    pub fn run(self, loaded: &Handoff<Instrument>, load: impl FnOnce(L) -> Option<Instrument>) {
+        match self {
+            Task::Load(request) => {
+                if let Some(instrument) = load(request) {
+                    // An instrument the audio thread didn't pick up yet is
+                    // dropped here.
+                    loaded.put(Box::new(instrument));
+                }
+            }
+            Task::Drop(instrument) => drop(instrument),
+        }
+    }
+}
+
+/// The instrument that is played, along with the ones on their way in and
+/// out, so instruments are never loaded or dropped on the audio thread.
+pub struct InstrumentSlot {
+    pub instrument: Box<Instrument>,
+    /// Instruments that were loaded on a background thread, waiting for the
+    /// audio thread to swap them in.
+    pub loaded: Arc<Handoff<Instrument>>,
+    /// The previous instrument, kept until its voices have faded out.
+    retiring: Option<Box<Instrument>>,
+}
+
+impl InstrumentSlot {
+    pub fn new() -> Self {
+        Self {
+            instrument: Box::default(),
+            loaded: Arc::new(Handoff::new()),
+            retiring: None,
+        }
+    }
+
+    /// Replaces the instrument with the one that was loaded, if there is one,
+    /// and stops the voices that play it. This must not be called on the
+    /// audio thread.
+    pub fn replace_loaded(&mut self, voices: &mut [&mut Voices]) {
+        if let Some(instrument) = self.loaded.take() {
+            voices.iter_mut().for_each(|voices| voices.clear());
+            self.retiring = None;
+            self.instrument = instrument;
+        }
+    }
+
+    /// Swaps in an instrument that was loaded in the background, fading out
+    /// the voices that still play the previous one. Returns an instrument
+    /// that is no longer needed and should be dropped off the audio thread.
+    pub fn swap_loaded(
+        &mut self,
+        voices: &mut [&mut Voices],
+        fade_out_samples: u32,
+    ) -> Option<Box<Instrument>> {
+        let instrument = self.loaded.take()?;
+
+        // If the instrument before the previous one is still fading out, its
+        // voices are cut short.
+        let retired = self.retiring.take();
+        for voices in voices {
+            if retired.is_some() {
+                voices.cut_fading();
+            }
+            voices.fade_out(fade_out_samples);
+        }
+        self.retiring = Some(std::mem::replace(&mut self.instrument, instrument));
+
+        retired
+    }
+
+    /// Returns the previous instrument once its voices are gone, so it can be
+    /// dropped off the audio thread.
+    pub fn take_retired(&mut self, voices: &[&Voices]) -> Option<Box<Instrument>> {
+        if voices.iter().any(|voices| voices.is_fading()) {
+            return None;
+        }
+
+        self.retiring.take()
+    }
+}
+
+impl Default for InstrumentSlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use instrument::Zone;
+
+    use super::*;
+    use crate::adsr::Adsr;
+    use crate::voice::Voice;
+
+    fn instrument(name: &str) -> Instrument {
+        Instrument {
+            name: name.to_string(),
+            zones: vec![Zone::new(60, Arc::new(vec![1.0; 100]))],
+            ..Instrument::default()
+        }
+    }
+
+    #[test]
+    fn retires_instruments_once_their_voices_faded() {
+        let mut slot = InstrumentSlot::new();
+        let mut voices = Voices::new(4);
+
+        Task::<()>::Load(()).run(&slot.loaded, |()| Some(instrument("First")));
+        slot.replace_loaded(&mut [&mut voices]);
+        assert_eq!(slot.instrument.name, "First");
+
+        let data = Arc::clone(&slot.instrument.zones[0].data);
+        voices.start(Voice::new(data, 60, 1.0, Adsr::new(44100.0), true), 2);
+        slot.loaded.put(Box::new(instrument("Second")));
+        assert!(slot.swap_loaded(&mut [&mut voices], 2).is_none());
+        assert_eq!(slot.instrument.name, "Second");
+        assert!(voices.is_fading());
+        assert!(slot.take_retired(&[&voices]).is_none());
+
+        for _ in 0..2 {
+            voices.next_sample();
+        }
+        voices.remove_finished();
+        assert_eq!(slot.take_retired(&[&voices]).unwrap().name, "First");
+        assert!(slot.take_retired(&[&voices]).is_none());
+    }
+}
@@ -1,18 +1,51 @@
+use std::ops::Range;
 use std::sync::Arc;
 
+use instrument::{Instrument, Zone};
+
 use crate::adsr::{Adsr, Envelope};
 
 pub struct Voice {
     sample_data: Arc<Vec<f32>>,
     note: u8,
-    position: usize,
+    /// The playback position in sample frames, between two frames when the
+    /// voice is repitched.
+    position: f64,
+    /// How many frames the position advances per output sample.
+    playback_rate: f64,
     velocity: f32,
     envelope: Envelope,
-    looping: bool,
+    /// The frames that are repeated once playback reaches the end of them.
+    loop_range: Option<Range<usize>>,
+    /// The gain of a fade-out started by [`Voice::fade_out`], independent of
+    /// the envelope.
+    fade_gain: f32,
+    /// How much `fade_gain` drops per sample, or 0 if the voice isn't fading.
+    fade_step: f32,
+    /// A factor on `playback_rate` that rises to 1 while the voice starts.
+    speed: f64,
+    /// How much `speed` rises per sample.
+    speed_step: f64,
+    /// How many samples are left before the voice stops by itself, and how
+    /// long it fades out then.
+    stop: Option<(u32, u32)>,
+    /// The damper that was put on the voice by [`Voice::damp`].
+    damper: Option<Damper>,
+}
+
+/// A damper on a ringing voice. Its gain decays exponentially and closes a
+/// low-pass filter along with it, so the highs die out first.
+struct Damper {
+    gain: f32,
+    decay: f32,
+    filter_state: f32,
 }
 
+/// The gain at which a damped voice is silent, which is -60 dB.
+const DAMPED_GAIN: f32 = 0.001;
+
 impl Voice {
-    /// Creates a new voice.
+    /// Creates a new voice. With `looping` the whole sample is repeated.
     pub fn new(
         sample_data: Arc<Vec<f32>>,
         note: u8,
@@ -20,14 +53,90 @@ impl Voice {
         adsr: Adsr,
         looping: bool,
     ) -> Self {
+        let loop_range = looping.then_some(0..sample_data.len());
+
         Self {
             sample_data,
             note,
-            position: 0,
+            position: 0.0,
+            playback_rate: 1.0,
             velocity,
             envelope: Envelope::new(adsr),
-            looping,
+            loop_range,
+            fade_gain: 1.0,
+            fade_step: 0.0,
+            speed: 1.0,
+            speed_step: 0.0,
+            stop: None,
+            damper: None,
+        }
+    }
+
+    /// Creates a voice for a key that plays a zone of an instrument at
+    /// `pitch`, in semitones like MIDI notes. The sample is repitched relative
+    /// to the zone's root note and fine tune and from the instrument's sample
+    /// rate to `sample_rate`, and the zone's loop is repeated.
+    pub fn from_zone(
+        instrument: &Instrument,
+        zone: &Zone,
+        note: u8,
+        pitch: f64,
+        velocity: f32,
+        adsr: Adsr,
+        sample_rate: f32,
+    ) -> Self {
+        let semitones = pitch - zone.root_note as f64 - zone.fine_tune as f64 / 100.0;
+        let playback_rate =
+            2.0_f64.powf(semitones / 12.0) * instrument.sample_rate as f64 / sample_rate as f64;
+
+        // Cloning the Arc is cheap (it just increments a reference count).
+        let voice = Self::new(Arc::clone(&zone.data), note, velocity, adsr, false)
+            .with_playback_rate(playback_rate);
+        match zone.loop_points {
+            Some(loop_points) => {
+                voice.with_loop(loop_points.start as usize..loop_points.end as usize)
+            }
+            None => voice,
+        }
+    }
+
+    /// Plays the sample faster or slower, which also changes its pitch. A rate
+    /// of 2.0 plays it an octave higher.
+    pub fn with_playback_rate(mut self, playback_rate: f64) -> Self {
+        self.playback_rate = playback_rate.max(0.0);
+        self
+    }
+
+    /// Repeats the frames in `loop_range` instead of the whole sample. Empty
+    /// ranges disable looping.
+    pub fn with_loop(mut self, loop_range: Range<usize>) -> Self {
+        let end = loop_range.end.min(self.sample_data.len());
+        self.loop_range = (loop_range.start < end).then_some(loop_range.start..end);
+        self
+    }
+
+    /// Starts playback at a fraction of the playback rate and speeds it up
+    /// to the full rate over a number of samples, like tape that needs to
+    /// get up to speed.
+    pub fn with_speed_up(mut self, start_speed: f64, samples: u32) -> Self {
+        if samples > 0 && start_speed < 1.0 {
+            self.speed = start_speed.max(0.0);
+            self.speed_step = (1.0 - self.speed) / samples as f64;
         }
+        self
+    }
+
+    /// Stops the voice after it played for a number of samples, fading it
+    /// out over `fade_samples`.
+    pub fn with_length(mut self, samples: u32, fade_samples: u32) -> Self {
+        self.stop = Some((samples, fade_samples));
+        self
+    }
+
+    /// Returns `true` if the voice is still going to stop by itself, see
+    /// [`Voice::with_length`].
+    pub fn has_length(&self) -> bool {
+        self.stop.is_some()
     }
 
     /// Checks if this voice is for a specific MIDI note.
@@ -35,20 +144,77 @@ impl Voice {
         self.note == note
     }
 
+    /// Returns the MIDI note of the key this voice is for.
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    /// Makes the voice louder while it plays, like a bell that rings along
+    /// with another one. `amount` is added to its velocity, up to 1.
+    pub fn excite(&mut self, amount: f32) {
+        self.velocity = (self.velocity + amount.max(0.0)).min(1.0);
+    }
+
     /// Triggers the release phase of the envelope.
     pub fn note_off(&mut self) {
         self.envelope.note_off();
     }
 
+    /// Damps the voice like a felt on a bell instead of releasing its
+    /// envelope: it decays by 60 dB over a number of samples, losing its highs
+    /// first.
+    pub fn damp(&mut self, samples: u32) {
+        if self.damper.is_none() {
+            self.damper = Some(Damper {
+                gain: 1.0,
+                decay: DAMPED_GAIN.powf(1.0 / samples.max(1) as f32),
+                filter_state: 0.0,
+            });
+        }
+    }
+
+    /// Returns `true` if [`Voice::damp`] was called.
+    pub fn is_damped(&self) -> bool {
+        self.damper.is_some()
+    }
+
+    /// Fades the voice out over a number of samples, regardless of its
+    /// envelope. This is used to stop voices without clicks, for example when
+    /// the instrument changes.
+    pub fn fade_out(&mut self, samples: u32) {
+        if !self.is_fading() {
+            self.fade_step = self.fade_gain / samples.max(1) as f32;
+        }
+    }
+
+    /// Returns `true` if [`Voice::fade_out`] was called.
+    pub fn is_fading(&self) -> bool {
+        self.fade_step > 0.0
+    }
+
     /// Returns `true` if the voice is still active.
     pub fn is_active(&self) -> bool {
-        if self.looping {
+        if self.fade_gain <= 0.0 || self.damper.as_ref().is_some_and(|d| d.gain < DAMPED_GAIN) {
+            false
+        } else if self.loop_range.is_some() {
             self.envelope.is_active()
         } else {
-            self.envelope.is_active() && self.position < self.sample_data.len()
+            self.envelope.is_active() && self.position < self.sample_data.len() as f64
         }
     }
 
+    /// Returns the frame at `index`, continuing in the loop past its end.
+    fn frame(&self, index: usize) -> f32 {
+        let index = match &self.loop_range {
+            Some(loop_range) if index >= loop_range.end => {
+                loop_range.start + (index - loop_range.end) % loop_range.len()
+            }
+            _ => index,
+        };
+
+        self.sample_data.get(index).copied().unwrap_or(0.0)
+    }
+
     /// Generates the next sample for this voice.
     pub fn next_sample(&mut self) -> f32 {
         if !self.is_active() {
@@ -57,13 +223,185 @@ impl Voice {
 
         let envelope_value = self.envelope.next_value();
 
-        if self.looping && !self.sample_data.is_empty() {
-            self.position %= self.sample_data.len();
+        // Interpolate between the frames around the position with a
+        // Catmull-Rom spline, which is exact if the position is on a frame.
+        let index = self.position as usize;
+        let t = (self.position - index as f64) as f32;
+        let mut sample_value = if t == 0.0 {
+            self.frame(index)
+        } else {
+            let y0 = index.checked_sub(1).map_or(0.0, |i| self.frame(i));
+            let y1 = self.frame(index);
+            let y2 = self.frame(index + 1);
+            let y3 = self.frame(index + 2);
+
+            let a = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+            let b = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+            let c = 0.5 * (y2 - y0);
+            ((a * t + b) * t + c) * t + y1
+        };
+
+        if let Some(damper) = &mut self.damper {
+            // The gain doubles as the coefficient of a one-pole low-pass, so
+            // the filter closes as the sound decays.
+            damper.filter_state += (sample_value - damper.filter_state) * damper.gain;
+            sample_value = damper.filter_state * damper.gain;
+            damper.gain *= damper.decay;
+        }
+
+        self.position += self.playback_rate * self.speed;
+        self.speed = (self.speed + self.speed_step).min(1.0);
+        if let Some(loop_range) = &self.loop_range {
+            let end = loop_range.end as f64;
+            if self.position >= end {
+                let length = loop_range.len() as f64;
+                self.position = loop_range.start as f64 + (self.position - end) % length;
+            }
+        }
+
+        match &mut self.stop {
+            Some((0, fade_samples)) => {
+                let fade_samples = *fade_samples;
+                self.stop = None;
+                self.fade_out(fade_samples);
+            }
+            Some((remaining, _)) => *remaining -= 1,
+            None => (),
+        }
+
+        let fade_gain = self.fade_gain;
+        self.fade_gain = (self.fade_gain - self.fade_step).max(0.0);
+
+        sample_value * self.velocity * envelope_value * fade_gain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use instrument::LoopPoints;
+
+    use super::*;
+
+    fn play(voice: &mut Voice, samples: usize) -> Vec<f32> {
+        (0..samples).map(|_| voice.next_sample()).collect()
+    }
+
+    fn ramp(length: usize) -> Arc<Vec<f32>> {
+        Arc::new((0..length).map(|i| i as f32 / 10.0).collect())
+    }
+
+    #[test]
+    fn repitches_the_sample() {
+        let mut voice = Voice::new(ramp(8), 60, 1.0, Adsr::new(44100.0), false);
+        assert_eq!(play(&mut voice, 4), [0.0, 0.1, 0.2, 0.3]);
+
+        // The spline follows a ramp exactly, apart from where it starts.
+        let mut voice =
+            Voice::new(ramp(8), 60, 1.0, Adsr::new(44100.0), false).with_playback_rate(0.5);
+        let output = play(&mut voice, 8);
+        for (i, sample) in output.iter().enumerate().skip(2) {
+            assert!((sample - i as f32 / 20.0).abs() < 1e-6, "{output:?}");
         }
 
-        let sample_value = self.sample_data.get(self.position).copied().unwrap_or(0.0);
-        self.position += 1;
+        let mut voice =
+            Voice::new(ramp(8), 60, 1.0, Adsr::new(44100.0), false).with_playback_rate(2.0);
+        assert_eq!(play(&mut voice, 4), [0.0, 0.2, 0.4, 0.6]);
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn plays_zones_at_their_pitch() {
+        let mut zone = Zone::new(60, ramp(100));
+        zone.fine_tune = 100.0;
+        zone.loop_points = Some(LoopPoints { start: 10, end: 20 });
+        let instrument = Instrument {
+            sample_rate: 44100,
+            channels: 1,
+            zones: vec![zone],
+            ..Instrument::default()
+        };
+        let zone = &instrument.zones[0];
+
+        // The zone sounds a semitone higher than its root note, and the host
+        // runs at half the instrument's sample rate.
+        let voice = Voice::from_zone(
+            &instrument,
+            zone,
+            73,
+            73.0,
+            1.0,
+            Adsr::new(22050.0),
+            22050.0,
+        );
+        assert!((voice.playback_rate - 4.0).abs() < 1e-9);
+        assert_eq!(voice.loop_range, Some(10..20));
+
+        let voice = Voice::from_zone(
+            &instrument,
+            zone,
+            61,
+            61.0,
+            1.0,
+            Adsr::new(44100.0),
+            44100.0,
+        );
+        assert!((voice.playback_rate - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn repeats_the_loop() {
+        let mut voice = Voice::new(ramp(6), 60, 1.0, Adsr::new(44100.0), false).with_loop(2..4);
+        assert_eq!(play(&mut voice, 7), [0.0, 0.1, 0.2, 0.3, 0.2, 0.3, 0.2]);
+        assert!(voice.is_active());
+
+        let mut voice = Voice::new(ramp(3), 60, 1.0, Adsr::new(44100.0), true);
+        assert_eq!(play(&mut voice, 5), [0.0, 0.1, 0.2, 0.0, 0.1]);
+
+        let voice = Voice::new(ramp(3), 60, 1.0, Adsr::new(44100.0), true).with_loop(3..5);
+        assert!(voice.loop_range.is_none());
+    }
+
+    #[test]
+    fn speeds_up_and_stops_like_tape() {
+        let mut voice = Voice::new(ramp(100), 60, 1.0, Adsr::new(44100.0), true)
+            .with_speed_up(0.5, 2)
+            .with_length(4, 2);
+        play(&mut voice, 4);
+        assert!((voice.position - 3.25).abs() < 1e-9);
+        assert!(voice.has_length());
+        assert!(!voice.is_fading());
+
+        // After its length, the voice fades out even though it loops.
+        let output = play(&mut voice, 3);
+        assert!(!voice.has_length());
+        assert!(voice.is_fading());
+        assert!(!voice.is_active());
+        assert!((output[1] - 0.5 * 0.425).abs() < 1e-6, "{output:?}");
+    }
+
+    #[test]
+    fn damping_decays_and_stops_the_voice() {
+        let data = Arc::new(vec![1.0; 100]);
+        let mut voice = Voice::new(data, 60, 1.0, Adsr::new(44100.0), true);
+        assert_eq!(play(&mut voice, 2), [1.0, 1.0]);
+
+        voice.damp(10);
+        assert!(voice.is_damped());
+        let output = play(&mut voice, 10);
+        assert_eq!(output[0], 1.0);
+        assert!(output.windows(2).all(|w| w[1] < w[0]), "{output:?}");
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn exciting_raises_the_velocity() {
+        let data = Arc::new(vec![1.0; 100]);
+        let mut voice = Voice::new(data, 60, 0.5, Adsr::new(44100.0), true);
+        assert_eq!(play(&mut voice, 1), [0.5]);
 
-        sample_value * self.velocity * envelope_value
+        voice.excite(0.25);
+        assert_eq!(play(&mut voice, 1), [0.75]);
+        voice.excite(1.0);
+        assert_eq!(play(&mut voice, 1), [1.0]);
     }
 }
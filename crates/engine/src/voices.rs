@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+
+use crate::voice::Voice;
+
+/// The voices that play an instrument at once.
+///
+/// Room for them is reserved up front, so starting notes doesn't allocate on
+/// the audio thread. Once every voice is taken, the oldest one is stolen and
+/// faded out rather than cut off with a click. Stolen voices keep playing
+/// while they fade, so there's room for as many of them again.
+pub struct Voices {
+    voices: VecDeque<Voice>,
+    max_voices: usize,
+}
+
+impl Voices {
+    pub fn new(max_voices: usize) -> Self {
+        Self {
+            voices: VecDeque::with_capacity(max_voices * 2),
+            max_voices,
+        }
+    }
+
+    /// Starts a voice, fading out the oldest one over a number of samples if
+    /// all of them are taken.
+    pub fn start(&mut self, voice: Voice, fade_out_samples: u32) {
+        let playing = self.voices.iter().filter(|v| !v.is_fading()).count();
+        if playing >= self.max_voices {
+            if let Some(oldest) = self.voices.iter_mut().find(|v| !v.is_fading()) {
+                oldest.fade_out(fade_out_samples);
+            }
+        }
+
+        // If even the fading voices use up all the room, the oldest of them is
+        // cut short.
+        if self.voices.len() >= self.max_voices * 2 {
+            let oldest = self.voices.iter().position(Voice::is_fading).unwrap_or(0);
+            self.voices.remove(oldest);
+        }
+        self.voices.push_back(voice);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Voice> {
+        self.voices.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Voice> {
+        self.voices.iter_mut()
+    }
+
+    /// Triggers the release phase of the voices for a MIDI note.
+    pub fn note_off(&mut self, note: u8) {
+        self.voices
+            .iter_mut()
+            .filter(|v| v.matches_note(note))
+            .for_each(Voice::note_off);
+    }
+
+    /// Fades out every voice over a number of samples.
+    pub fn fade_out(&mut self, samples: u32) {
+        for voice in &mut self.voices {
+            voice.fade_out(samples);
+        }
+    }
+
+    /// Returns `true` if any voice is fading out.
+    pub fn is_fading(&self) -> bool {
+        self.voices.iter().any(Voice::is_fading)
+    }
+
+    /// Stops the voices that are fading out right away.
+    pub fn cut_fading(&mut self) {
+        self.voices.retain(|v| !v.is_fading());
+    }
+
+    /// Sums the next sample of all voices.
+    pub fn next_sample(&mut self) -> f32 {
+        self.voices.iter_mut().map(Voice::next_sample).sum()
+    }
+
+    /// Removes the voices that ended.
+    pub fn remove_finished(&mut self) {
+        self.voices.retain(Voice::is_active);
+    }
+
+    pub fn len(&self) -> usize {
+        self.voices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.voices.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::adsr::Adsr;
+
+    fn voice(note: u8) -> Voice {
+        Voice::new(
+            Arc::new(vec![1.0; 100]),
+            note,
+            1.0,
+            Adsr::new(44100.0),
+            true,
+        )
+    }
+
+    #[test]
+    fn fades_out_stolen_voices() {
+        let mut voices = Voices::new(2);
+        let capacity = voices.voices.capacity();
+        voices.start(voice(60), 4);
+        voices.start(voice(61), 4);
+        assert!(!voices.is_fading());
+
+        // The oldest voice fades out instead of stopping right away.
+        voices.start(voice(62), 4);
+        assert_eq!(voices.len(), 3);
+        assert!(voices.iter().next().unwrap().is_fading());
+        assert_eq!(voices.iter().filter(|v| v.is_fading()).count(), 1);
+        assert_eq!(voices.next_sample(), 3.0);
+        for _ in 0..4 {
+            voices.next_sample();
+        }
+        voices.remove_finished();
+        assert_eq!(voices.len(), 2);
+        assert!(!voices.is_fading());
+
+        // Once the fading voices fill up the rest, the oldest of them goes.
+        for note in 63..70 {
+            voices.start(voice(note), 4);
+        }
+        assert_eq!(voices.len(), 4);
+        assert_eq!(voices.iter().filter(|v| !v.is_fading()).count(), 2);
+        assert_eq!(voices.voices.capacity(), capacity);
+    }
+}
@@ -191,6 +191,12 @@ impl BiquadFilter {
         }
     }
 
+    /// Clears the unit delays.
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
     pub fn process(&mut self, input: f32) -> f32 {
         let output = input * self.a0 + self.z1;
         self.z1 = input * self.a1 + self.z2 - self.b1 * output;
@@ -247,6 +253,12 @@ impl StereoBiquadFilter {
         self.filter_r.set_peak_gain(peak_gain);
     }
 
+    /// Clears the unit delays of both filters.
+    pub fn reset(&mut self) {
+        self.filter_l.reset();
+        self.filter_r.reset();
+    }
+
     pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
         let out_l = self.filter_l.process(input.0);
         let out_r = self.filter_r.process(input.1);
@@ -62,6 +62,21 @@ impl StereoDelay {
         self.buffer_r.resize(new_size, 0.0);
     }
 
+    ///
+    /// Clears the buffers and the LFO without reallocating them.
+    pub fn reset(&mut self) {
+        self.buffer_l.fill(0.0);
+        self.buffer_r.fill(0.0);
+        self.write_pointer = 0;
+        self.lfo_phase = 0.0;
+    }
+
+    ///
+    /// Sets the phase of the LFO, in cycles.
+    pub fn set_lfo_phase(&mut self, phase: f32) {
+        self.lfo_phase = phase.rem_euclid(1.0);
+    }
+
     ///
     /// Calculates value at time `t` using cubic interpolation.
     fn get_cubic_interpolated_value_from_buffer(&self, t: f32, buffer: &[f32]) -> f32 {
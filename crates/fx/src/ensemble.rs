@@ -0,0 +1,88 @@
+use crate::delay_line::StereoDelay;
+
+/// The number of chorus voices.
+const VOICES: usize = 3;
+/// The widest delay sweep of a voice, in seconds.
+const MAX_WIDTH_SECONDS: f32 = 0.005;
+/// The delay buffer of a voice, in seconds. It has some room to spare over the
+/// widest sweep.
+const BUFFER_SECONDS: f32 = 0.02;
+
+/// A string ensemble: several chorus voices whose LFOs are spread evenly over
+/// a cycle, like the bucket brigade ensembles of string machines. The right
+/// channel of every voice is half a cycle apart from the left one, which
+/// turns a mono signal into a wide stereo one.
+pub struct Ensemble {
+    voices: [StereoDelay; VOICES],
+}
+
+impl Ensemble {
+    pub fn new(sample_rate: f32) -> Ensemble {
+        let mut ensemble = Ensemble {
+            voices: std::array::from_fn(|_| StereoDelay::new(BUFFER_SECONDS, sample_rate as usize)),
+        };
+        ensemble.reset();
+        ensemble
+    }
+
+    /// Clears the delay lines and spreads the LFOs again.
+    pub fn reset(&mut self) {
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            voice.reset();
+            voice.set_lfo_phase(i as f32 / VOICES as f32);
+        }
+    }
+
+    ///
+    /// Process a sample.
+    ///
+    /// # Arguments
+    /// * `input` - the left and right sample
+    /// * `rate` - the frequency of the LFOs, in Hz
+    /// * `depth` - how far the voices are detuned, from 0 to 1
+    /// * `mix` - the level of the voices against the dry signal, from 0 to 1
+    pub fn process(&mut self, input: (f32, f32), rate: f32, depth: f32, mix: f32) -> (f32, f32) {
+        let width = depth.clamp(0.0, 1.0) * MAX_WIDTH_SECONDS;
+        let mut wet = (0.0, 0.0);
+        for voice in &mut self.voices {
+            let (l, r) = voice.process_with_vibrato(input, rate, width, 0.5);
+            wet.0 += l;
+            wet.1 += r;
+        }
+
+        let wet_gain = mix / VOICES as f32;
+        (
+            input.0 * (1.0 - mix) + wet.0 * wet_gain,
+            input.1 * (1.0 - mix) + wet.1 * wet_gain,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_signal_passes_without_mix() {
+        let mut ensemble = Ensemble::new(44100.0);
+        for i in 0..1000 {
+            let input = (i as f32 * 0.05).sin();
+            assert_eq!(
+                ensemble.process((input, input), 0.6, 1.0, 0.0),
+                (input, input)
+            );
+        }
+    }
+
+    #[test]
+    fn mono_input_becomes_stereo() {
+        let mut ensemble = Ensemble::new(44100.0);
+        let mut difference = 0.0_f32;
+        for i in 0..44100 {
+            let input = (i as f32 * 0.05).sin();
+            let (l, r) = ensemble.process((input, input), 0.6, 1.0, 1.0);
+            difference = difference.max((l - r).abs());
+        }
+        assert!(difference > 0.01);
+    }
+}
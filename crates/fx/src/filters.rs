@@ -21,6 +21,12 @@ impl DelayLine {
         self.buffer[self.index]
     }
 
+    /// Clears the buffer without reallocating it.
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.);
+        self.index = 0;
+    }
+
     pub fn write_and_advance(&mut self, value: f32) {
         self.buffer[self.index] = value;
 
@@ -45,6 +51,10 @@ impl Allpass {
         }
     }
 
+    pub fn reset(&mut self) {
+        self.delay_line.reset();
+    }
+
     ///
     /// Process an input value with output and feedback
     /// calculated in the style of Schroeder's allpass filter.
@@ -91,6 +101,11 @@ impl Comb {
         self.dampening_inverse = 1.0 - value;
     }
 
+    pub fn reset(&mut self) {
+        self.delay_line.reset();
+        self.filter_state = 0.;
+    }
+
     pub fn tick(&mut self, input: f32) -> f32 {
         let output = self.delay_line.read();
         self.filter_state = output * self.dampening_inverse + self.filter_state * self.dampening;
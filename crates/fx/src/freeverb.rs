@@ -147,11 +147,35 @@ impl Freeverb {
         self.allpasses = generate_allpass_filters(sr);
     }
 
+    /// Clears the reverb tail without reallocating the filters.
+    pub fn reset(&mut self) {
+        for combs in self.combs.iter_mut() {
+            combs.0.reset();
+            combs.1.reset();
+        }
+        for allpasses in self.allpasses.iter_mut() {
+            allpasses.0.reset();
+            allpasses.1.reset();
+        }
+    }
+
     pub fn set_wet(&mut self, value: f32) {
         self.wet = value * SCALE_WET;
         self.update_wet_gains();
     }
 
+    pub fn set_dry(&mut self, value: f32) {
+        self.dry = value;
+    }
+
+    /// Blends the reverb with the dry input, from 0.0 for only the input to
+    /// 1.0 for only the reverb. By default, [`tick`](Self::tick) only returns
+    /// the reverb.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.set_dry(1.0 - mix);
+        self.set_wet(mix);
+    }
+
     pub fn set_width(&mut self, value: f32) {
         self.width = value;
         self.update_wet_gains();
@@ -215,3 +239,18 @@ impl Freeverb {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixes_with_the_dry_input() {
+        let mut freeverb = Freeverb::new(44100);
+        assert_eq!(freeverb.tick((1.0, 0.5)), (0.0, 0.0));
+
+        freeverb.reset();
+        freeverb.set_mix(0.0);
+        assert_eq!(freeverb.tick((1.0, 0.5)), (1.0, 0.5));
+    }
+}
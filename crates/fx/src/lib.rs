@@ -3,10 +3,14 @@ pub mod dc_filter;
 pub mod delay_line;
 pub mod digital;
 pub mod dynamics;
+pub mod ensemble;
 pub mod filters;
 pub mod freeverb;
+pub mod limiter;
 pub mod moorer_verb;
 pub mod oversampling;
+#[cfg(feature = "plugin")]
+pub mod params;
 pub mod waveshapers;
 
 // Constants for tape-modeled vibrato (wow & flutter)
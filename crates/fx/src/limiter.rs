@@ -0,0 +1,98 @@
+/// A stereo peak limiter that keeps the output at or below a threshold.
+///
+/// The gain drops instantly when a peak goes over the threshold, so nothing
+/// ever passes it, and recovers smoothly over the release time. Both channels
+/// share the gain, so the stereo image doesn't shift.
+pub struct Limiter {
+    sample_rate: f32,
+    threshold: f32,
+    release_coefficient: f32,
+    envelope: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: f32) -> Limiter {
+        let mut limiter = Limiter {
+            sample_rate,
+            threshold: 1.0,
+            release_coefficient: 0.0,
+            envelope: 0.0,
+        };
+        limiter.set_parameters(0.0, 0.1);
+        limiter
+    }
+
+    ///
+    /// Update the parameters of the limiter.
+    ///
+    /// # Arguments
+    /// * `threshold` - the highest output level, in dBFS
+    /// * `release` - the time it takes for the gain to recover, in seconds
+    pub fn set_parameters(&mut self, threshold: f32, release: f32) {
+        self.threshold = 10.0_f32.powf(threshold / 20.0);
+        self.release_coefficient = if release > 0.0 {
+            (-1.0 / (release * self.sample_rate)).exp()
+        } else {
+            0.0
+        };
+    }
+
+    /// Clears the envelope, so the gain starts at unity again.
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let peak = input.0.abs().max(input.1.abs());
+        self.envelope = peak.max(self.envelope * self.release_coefficient);
+
+        if self.envelope > self.threshold {
+            let gain = self.threshold / self.envelope;
+            (input.0 * gain, input.1 * gain)
+        } else {
+            input
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_never_exceeds_threshold() {
+        let mut limiter = Limiter::new(44100.0);
+        limiter.set_parameters(-6.0, 0.05);
+        let threshold = 10.0_f32.powf(-6.0 / 20.0);
+
+        for i in 0..44100 {
+            let input = (i as f32 * 0.01).sin() * 2.0;
+            let (l, r) = limiter.process((input, -input * 0.5));
+            assert!(l.abs() <= threshold + 1e-6);
+            assert!(r.abs() <= threshold + 1e-6);
+        }
+    }
+
+    #[test]
+    fn quiet_signals_pass_unchanged() {
+        let mut limiter = Limiter::new(44100.0);
+        limiter.set_parameters(0.0, 0.1);
+        assert_eq!(limiter.process((0.5, -0.25)), (0.5, -0.25));
+    }
+
+    #[test]
+    fn gain_recovers_after_release() {
+        let mut limiter = Limiter::new(1000.0);
+        limiter.set_parameters(0.0, 0.01);
+        limiter.process((4.0, 4.0));
+        assert_eq!(
+            limiter.process((0.5, 0.5)).0,
+            0.5 / (4.0 * (-0.1_f32).exp())
+        );
+
+        for _ in 0..200 {
+            limiter.process((0.0, 0.0));
+        }
+        assert_eq!(limiter.process((0.5, 0.5)), (0.5, 0.5));
+    }
+}
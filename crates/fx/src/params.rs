@@ -0,0 +1,61 @@
+//! Parameters for the effects in this crate that several plugins share.
+
+use nih_plug::prelude::*;
+
+use crate::freeverb::Freeverb;
+
+/// Creates a parameter that goes from 0% to 100%.
+pub fn percentage(name: &str, default: f32) -> FloatParam {
+    FloatParam::new(name, default, FloatRange::Linear { min: 0.0, max: 1.0 })
+        .with_unit("%")
+        .with_value_to_string(formatters::v2s_f32_percentage(0))
+        .with_string_to_value(formatters::s2v_f32_percentage())
+}
+
+#[derive(Params)]
+pub struct ReverbParams {
+    #[id = "reverb"]
+    pub enabled: BoolParam,
+    #[id = "reverb-size"]
+    pub size: FloatParam,
+    #[id = "reverb-damping"]
+    pub damping: FloatParam,
+    #[id = "reverb-width"]
+    pub width: FloatParam,
+    #[id = "reverb-mix"]
+    pub mix: FloatParam,
+}
+
+impl Default for ReverbParams {
+    fn default() -> Self {
+        Self {
+            enabled: BoolParam::new("Reverb", false),
+            size: percentage("Reverb Size", 0.5),
+            damping: percentage("Reverb Damping", 0.5),
+            width: percentage("Reverb Width", 1.0),
+            mix: percentage("Reverb Mix", 0.25),
+        }
+    }
+}
+
+impl ReverbParams {
+    /// Updates a reverb from the parameters. This is done once per block,
+    /// since it retunes all of its filters.
+    pub fn update(&self, reverb: &mut Freeverb) {
+        if self.enabled.value() {
+            reverb.set_room_size(self.size.value());
+            reverb.set_damping(self.damping.value());
+            reverb.set_width(self.width.value());
+            reverb.set_mix(self.mix.value());
+        }
+    }
+
+    /// Runs a stereo sample through the reverb if it's enabled.
+    pub fn process(&self, reverb: &mut Freeverb, input: (f32, f32)) -> (f32, f32) {
+        if self.enabled.value() {
+            reverb.tick(input)
+        } else {
+            input
+        }
+    }
+}
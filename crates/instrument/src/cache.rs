@@ -0,0 +1,266 @@
+//! Instruments that were resampled for a host sample rate, kept in memory and
+//! optionally on disk, so switching presets or reopening a project doesn't
+//! resample them again.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::Instrument;
+
+/// How many instruments are kept in memory. The least recently used one is
+/// evicted first.
+const MEMORY_CAPACITY: usize = 8;
+
+/// The metadata key cached files store their cache key under, which tells
+/// them apart if two keys hash to the same file name.
+const CACHE_KEY: &str = "zmann_cache_key";
+
+/// Returns the directory a plugin caches resampled instruments in:
+///
+/// - Windows: `%LOCALAPPDATA%\ZMANN\<plugin>`
+/// - macOS: `~/Library/Caches/ZMANN/<plugin>`
+/// - Linux: `$XDG_CACHE_HOME/ZMANN/<plugin>`, or `~/.cache/ZMANN/<plugin>`
+pub fn user_cache_dir(plugin: &str) -> Option<PathBuf> {
+    let env = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+
+    let cache_dir = if cfg!(windows) {
+        env("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env("HOME").map(|home| Path::new(&home).join("Library/Caches"))
+    } else {
+        env("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+
+    cache_dir.map(|dir| dir.join("ZMANN").join(plugin))
+}
+
+/// Returns a cache key for an instrument file, which changes when the file
+/// is modified.
+pub fn file_key(path: &Path) -> std::io::Result<String> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok(format!(
+        "file/{}/{}/{}",
+        path.display(),
+        metadata.len(),
+        modified.as_nanos()
+    ))
+}
+
+/// Resampled instruments by a key that identifies their source, and the
+/// sample rate they were resampled to.
+///
+/// Cloning an [`Instrument`] only clones references to its sample data, so
+/// hits are cheap. Disk errors are ignored, since the instrument can always be
+/// loaded again.
+pub struct InstrumentCache {
+    dir: Option<PathBuf>,
+    /// The most recently used instrument comes last.
+    memory: Mutex<Vec<(String, u32, Instrument)>>,
+}
+
+impl InstrumentCache {
+    /// Creates a cache that also stores instruments in `dir`, if there is one.
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            memory: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the instrument for `key` at `sample_rate`, or calls `load` to
+    /// create it and caches the result.
+    ///
+    /// `key` must change whenever the instrument does, for example by
+    /// including the plugin version for built-in presets or [`file_key`] for
+    /// files.
+    pub fn get_or_insert_with<E>(
+        &self,
+        key: &str,
+        sample_rate: u32,
+        load: impl FnOnce() -> Result<Instrument, E>,
+    ) -> Result<Instrument, E> {
+        if let Some(instrument) = self.get_memory(key, sample_rate) {
+            return Ok(instrument);
+        }
+
+        let path = self.path(key, sample_rate);
+        let instrument = match path.as_deref().and_then(|path| read(path, key)) {
+            Some(instrument) => instrument,
+            None => {
+                let instrument = load()?;
+                if let Some(path) = &path {
+                    write(path, key, &instrument);
+                }
+                instrument
+            }
+        };
+
+        self.insert_memory(key, sample_rate, instrument.clone());
+        Ok(instrument)
+    }
+
+    fn get_memory(&self, key: &str, sample_rate: u32) -> Option<Instrument> {
+        let mut memory = self.memory.lock().unwrap();
+        let index = memory
+            .iter()
+            .position(|(k, rate, _)| k == key && *rate == sample_rate)?;
+
+        let entry = memory.remove(index);
+        let instrument = entry.2.clone();
+        memory.push(entry);
+
+        Some(instrument)
+    }
+
+    fn insert_memory(&self, key: &str, sample_rate: u32, instrument: Instrument) {
+        let mut memory = self.memory.lock().unwrap();
+        memory.retain(|(k, rate, _)| k != key || *rate != sample_rate);
+        if memory.len() >= MEMORY_CAPACITY {
+            memory.remove(0);
+        }
+        memory.push((key.to_string(), sample_rate, instrument));
+    }
+
+    fn path(&self, key: &str, sample_rate: u32) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(format!(
+            "{:016x}-{}.{}",
+            fnv1a(key.as_bytes()),
+            sample_rate,
+            crate::library::INSTRUMENT_EXTENSION
+        )))
+    }
+}
+
+/// Reads a cached instrument, if the file exists and belongs to `key`.
+fn read(path: &Path, key: &str) -> Option<Instrument> {
+    let mut instrument = Instrument::decode(&std::fs::read(path).ok()?).ok()?;
+    if instrument.metadata.remove(CACHE_KEY).as_deref() != Some(key) {
+        return None;
+    }
+
+    Some(instrument)
+}
+
+/// Writes an instrument to the cache. It's written to a temporary file first,
+/// so other instances never read a partially written file.
+fn write(path: &Path, key: &str, instrument: &Instrument) {
+    let mut instrument = instrument.clone();
+    instrument
+        .metadata
+        .insert(CACHE_KEY.to_string(), key.to_string());
+    // Resampled sample data barely compresses, so this favors speed.
+    let bin = Instrument::encode_with_level(instrument, 1);
+
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    let written = path
+        .parent()
+        .is_some_and(|dir| std::fs::create_dir_all(dir).is_ok())
+        && std::fs::write(&temp_path, bin).is_ok()
+        && std::fs::rename(&temp_path, path).is_ok();
+    if !written {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+}
+
+/// The 64-bit FNV-1a hash, which unlike the standard library's hasher is the
+/// same across Rust versions, so file names stay valid.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::Zone;
+
+    fn instrument(sample_rate: u32) -> Result<Instrument, ()> {
+        Ok(Instrument {
+            name: "Pad".to_string(),
+            sample_rate,
+            channels: 1,
+            zones: vec![Zone::new(60, Arc::new(vec![0.25; 32]))],
+            ..Instrument::default()
+        })
+    }
+
+    #[test]
+    fn caches_in_memory_and_on_disk() {
+        let dir = std::env::temp_dir().join(format!("zmann-cache-{}", std::process::id()));
+        let loads = Cell::new(0);
+        let load = |sample_rate| {
+            loads.set(loads.get() + 1);
+            instrument(sample_rate)
+        };
+
+        let cache = InstrumentCache::new(Some(dir.clone()));
+        cache
+            .get_or_insert_with("pad", 48000, || load(48000))
+            .unwrap();
+        cache
+            .get_or_insert_with("pad", 48000, || load(48000))
+            .unwrap();
+        assert_eq!(loads.get(), 1);
+
+        // Other sample rates and keys are separate entries.
+        cache
+            .get_or_insert_with("pad", 96000, || load(96000))
+            .unwrap();
+        cache
+            .get_or_insert_with("lead", 48000, || load(48000))
+            .unwrap();
+        assert_eq!(loads.get(), 3);
+
+        // A new cache, like in a new session, reads the files.
+        let cache = InstrumentCache::new(Some(dir.clone()));
+        let cached = cache
+            .get_or_insert_with("pad", 96000, || load(96000))
+            .unwrap();
+        assert_eq!(loads.get(), 3);
+        assert_eq!(cached.sample_rate, 96000);
+        assert_eq!(*cached.zones[0].data, [0.25; 32]);
+        assert!(cached.metadata.is_empty());
+
+        // Errors aren't cached.
+        assert!(cache
+            .get_or_insert_with("broken", 48000, || Err(()))
+            .is_err());
+        cache
+            .get_or_insert_with("broken", 48000, || load(48000))
+            .unwrap();
+        assert_eq!(loads.get(), 4);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_instrument() {
+        let cache = InstrumentCache::new(None);
+        for rate in 0..MEMORY_CAPACITY as u32 {
+            cache
+                .get_or_insert_with("pad", rate, || instrument(rate))
+                .unwrap();
+        }
+        // Using the first entry makes the second one the oldest.
+        cache.get_or_insert_with("pad", 0, || Err(())).unwrap();
+        cache
+            .get_or_insert_with("lead", 0, || instrument(0))
+            .unwrap();
+
+        assert!(cache.get_or_insert_with("pad", 0, || Err(())).is_ok());
+        assert!(cache.get_or_insert_with("pad", 1, || Err(())).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+use std::mem;
+use std::ops::Deref;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+use std::sync::Arc;
+
+use rkyv::AlignedVec;
+
+use crate::format::{
+    read_u32_le,
+    validate_layout,
+    validate_zone,
+    ArchivedIndex,
+    ArchivedZoneEntry,
+    Index,
+    HEADER_LEN,
+};
+use crate::{DecodeError, Instrument, LoopPoints, Zone, FORMAT_VERSION, MAGIC};
+
+/// Where the archived index is read from.
+enum IndexBytes {
+    /// Straight from the encoded bytes.
+    InPlace,
+    /// The index wasn't aligned in the encoded bytes, so it was copied once.
+    Copied(AlignedVec),
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Holders of encoded bytes that an [`InstrumentFile`] can read from.
+///
+/// The index is only validated once, so this is sealed to holders that
+/// always deref to the same bytes: a `Vec<u8>`, a `&[u8]` such as the
+/// `&'static [u8]` from `include_bytes!()` or, with the `mmap` feature, a
+/// memory-mapped file.
+pub trait EncodedBytes: Deref<Target = [u8]> + sealed::Sealed {}
+
+impl sealed::Sealed for Vec<u8> {}
+impl EncodedBytes for Vec<u8> {}
+
+impl sealed::Sealed for &[u8] {}
+impl EncodedBytes for &[u8] {}
+
+#[cfg(feature = "mmap")]
+impl sealed::Sealed for memmap2::Mmap {}
+#[cfg(feature = "mmap")]
+impl EncodedBytes for memmap2::Mmap {}
+
+/// An encoded instrument that is read without decoding it up front.
+///
+/// The index is validated once and then read in place, and every zone is
+/// only decompressed when it is requested.
+pub struct InstrumentFile<B> {
+    bytes: B,
+    index: IndexBytes,
+    /// The position of the first zone chunk in `bytes`.
+    chunks_start: usize,
+}
+
+impl<B: EncodedBytes> InstrumentFile<B> {
+    /// Validates the header and the index of an encoded instrument.
+    ///
+    /// The zone chunks are checked when they are decompressed.
+    pub fn new(bytes: B) -> Result<Self, DecodeError> {
+        let bin = &*bytes;
+        if bin.len() < HEADER_LEN {
+            return Err(if bin.starts_with(&MAGIC[..bin.len().min(4)]) {
+                DecodeError::Truncated
+            } else {
+                DecodeError::BadMagic
+            });
+        }
+        if bin[0..4] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+
+        let version = read_u32_le(bin, 4);
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let chunks_start = HEADER_LEN
+            .checked_add(read_u32_le(bin, 8) as usize)
+            .filter(|&end| end <= bin.len())
+            .ok_or(DecodeError::Truncated)?;
+        let index_bytes = &bin[HEADER_LEN..chunks_start];
+
+        let expected = read_u32_le(bin, 12);
+        let actual = crc32fast::hash(index_bytes);
+        if expected != actual {
+            return Err(DecodeError::ChecksumMismatch { expected, actual });
+        }
+
+        let index = if is_aligned(index_bytes) {
+            IndexBytes::InPlace
+        } else {
+            let mut copy = AlignedVec::with_capacity(index_bytes.len());
+            copy.extend_from_slice(index_bytes);
+            IndexBytes::Copied(copy)
+        };
+
+        let file = Self {
+            index,
+            chunks_start,
+            bytes,
+        };
+        rkyv::check_archived_root::<Index>(file.index_bytes())
+            .map_err(|e| DecodeError::Invalid(e.to_string()))?;
+        file.validate()?;
+
+        Ok(file)
+    }
+
+    /// The instrument's name.
+    pub fn name(&self) -> &str {
+        self.index().name.as_str()
+    }
+
+    /// The sample rate of all zones in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.index().sample_rate
+    }
+
+    /// The number of interleaved channels in every zone's data.
+    pub fn channels(&self) -> u16 {
+        self.index().channels
+    }
+
+    /// Iterates over the instrument's metadata as key-value pairs.
+    pub fn metadata(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.index()
+            .metadata
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The number of zones in the instrument.
+    pub fn zone_count(&self) -> usize {
+        self.index().zones.len()
+    }
+
+    /// Returns the index of the first zone that covers a note at the given
+    /// velocity, without decompressing anything.
+    ///
+    /// `velocity` is normalized to `[0.0, 1.0]`, like in NIH-plug's note
+    /// events.
+    pub fn find_zone(&self, note: u8, velocity: f32) -> Option<usize> {
+        let velocity = (velocity.clamp(0.0, 1.0) * 127.0).round() as u8;
+        self.index().zones.iter().position(|entry| {
+            (entry.low_key..=entry.high_key).contains(&note)
+                && (entry.low_velocity..=entry.high_velocity).contains(&velocity)
+        })
+    }
+
+    /// Decompresses a single zone.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `index` is out of bounds.
+    pub fn zone(&self, index: usize) -> Result<Zone, DecodeError> {
+        let entry = &self.index().zones[index];
+        // The bounds were checked in `new`.
+        let start = self.chunks_start + entry.offset as usize;
+        let chunk = &self.bytes[start..start + entry.len as usize];
+
+        let actual = crc32fast::hash(chunk);
+        if entry.checksum != actual {
+            return Err(DecodeError::ChecksumMismatch {
+                expected: entry.checksum,
+                actual,
+            });
+        }
+
+        // The frame header tells how large the zone really is, which has to
+        // match its index entry before anything is allocated for it.
+        let size = entry.samples * mem::size_of::<f32>() as u64;
+        match zstd::zstd_safe::get_frame_content_size(chunk) {
+            Ok(Some(content_size)) if content_size == size => (),
+            _ => {
+                return Err(DecodeError::Malformed(
+                    "zone data doesn't match its index entry",
+                ))
+            }
+        }
+
+        let mut data = vec![0.0f32; entry.samples as usize];
+        // SAFETY: Every bit pattern is a valid `f32`, and `u8` has no alignment
+        // requirements. The size doesn't overflow, that was checked in `new`.
+        let raw = unsafe {
+            std::slice::from_raw_parts_mut(
+                data.as_mut_ptr().cast::<u8>(),
+                data.len() * mem::size_of::<f32>(),
+            )
+        };
+        let len = zstd::bulk::decompress_to_buffer(chunk, raw).map_err(DecodeError::Decompress)?;
+        if len != raw.len() {
+            return Err(DecodeError::Malformed(
+                "zone data is shorter than its index entry",
+            ));
+        }
+        // The samples are stored little-endian, so this is free on most
+        // targets.
+        for sample in &mut data {
+            *sample = f32::from_bits(u32::from_le(sample.to_bits()));
+        }
+
+        Ok(Zone {
+            root_note: entry.root_note,
+            fine_tune: entry.fine_tune,
+            key_range: entry.low_key..=entry.high_key,
+            velocity_range: entry.low_velocity..=entry.high_velocity,
+            loop_points: loop_points(entry),
+            data: Arc::new(data),
+        })
+    }
+
+    /// Decompresses every zone into an [`Instrument`].
+    pub fn load(&self) -> Result<Instrument, DecodeError> {
+        let index = self.index();
+
+        Ok(Instrument {
+            name: index.name.to_string(),
+            sample_rate: index.sample_rate,
+            channels: index.channels,
+            zones: (0..index.zones.len())
+                .map(|i| self.zone(i))
+                .collect::<Result<_, _>>()?,
+            metadata: self
+                .metadata()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        })
+    }
+
+    fn index_bytes(&self) -> &[u8] {
+        match &self.index {
+            IndexBytes::InPlace => {
+                let bytes = &self.bytes[HEADER_LEN..self.chunks_start];
+                // Moving `B` into `Self` must not have moved the bytes.
+                assert!(is_aligned(bytes));
+                bytes
+            }
+            IndexBytes::Copied(copy) => copy,
+        }
+    }
+
+    fn index(&self) -> &ArchivedIndex {
+        // SAFETY: The index was validated in `new`. `B` is an `EncodedBytes`,
+        // which always derefs to the same bytes, and we only ever hand out
+        // shared references to them.
+        unsafe { rkyv::archived_root::<Index>(self.index_bytes()) }
+    }
+
+    /// Checks that every zone is playable and that its chunk is in bounds,
+    /// so `zone()` doesn't have to.
+    fn validate(&self) -> Result<(), DecodeError> {
+        let index = self.index();
+        validate_layout(index.sample_rate, index.channels)?;
+
+        let chunks_len = (self.bytes.len() - self.chunks_start) as u64;
+        for entry in index.zones.iter() {
+            let samples = usize::try_from(entry.samples)
+                .ok()
+                .filter(|samples| samples.checked_mul(mem::size_of::<f32>()).is_some())
+                .ok_or(DecodeError::Malformed("zone is too large"))?;
+
+            validate_zone(
+                entry.root_note,
+                entry.fine_tune,
+                &(entry.low_key..=entry.high_key),
+                &(entry.low_velocity..=entry.high_velocity),
+                loop_points(entry),
+                samples,
+                index.channels,
+            )?;
+
+            match entry.offset.checked_add(entry.len) {
+                Some(end) if end <= chunks_len => (),
+                _ => return Err(DecodeError::Truncated),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl InstrumentFile<memmap2::Mmap> {
+    /// Memory-maps an encoded instrument, so zones are only read from disk
+    /// when they are decompressed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DecodeError> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: Instrument files are never written to while they're in use.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Self::new(mmap)
+    }
+}
+
+fn is_aligned(bytes: &[u8]) -> bool {
+    (bytes.as_ptr() as usize).is_multiple_of(mem::align_of::<ArchivedIndex>())
+}
+
+fn loop_points(entry: &ArchivedZoneEntry) -> Option<LoopPoints> {
+    entry.loop_points.as_ref().map(|loop_points| LoopPoints {
+        start: loop_points.start,
+        end: loop_points.end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::format::ZoneEntry;
+
+    fn encoded() -> Vec<u8> {
+        let mut low = Zone::new(48, Arc::new(vec![0.0, 0.5, -0.5]));
+        low.key_range = 0..=59;
+        let mut high = Zone::new(72, Arc::new(vec![0.25; 1000]));
+        high.key_range = 60..=127;
+
+        Instrument::encode(Instrument {
+            name: "Lazy".to_string(),
+            sample_rate: 44100,
+            channels: 1,
+            zones: vec![low, high],
+            metadata: BTreeMap::from([("author".to_string(), "ZMANN".to_string())]),
+        })
+    }
+
+    #[test]
+    fn reads_the_index_without_decompressing() {
+        let mut bin = encoded();
+        // Break the second zone's chunk, which sits at the very end.
+        let last = bin.len() - 1;
+        bin[last] ^= 0xff;
+
+        let file = InstrumentFile::new(bin).unwrap();
+        assert_eq!(file.name(), "Lazy");
+        assert_eq!(file.sample_rate(), 44100);
+        assert_eq!(file.metadata().collect::<Vec<_>>(), [("author", "ZMANN")]);
+        assert_eq!(file.find_zone(80, 1.0), Some(1));
+
+        assert_eq!(file.zone(0).unwrap().data.as_slice(), [0.0, 0.5, -0.5]);
+        assert!(matches!(
+            file.zone(1),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_zones_larger_than_their_data() {
+        let chunk = zstd::bulk::compress(&[0; 12], 3).unwrap();
+        let index = Index {
+            name: "Liar".to_string(),
+            sample_rate: 44100,
+            channels: 1,
+            zones: vec![ZoneEntry {
+                root_note: 60,
+                fine_tune: 0.0,
+                low_key: 0,
+                high_key: 127,
+                low_velocity: 0,
+                high_velocity: 127,
+                loop_points: None,
+                samples: 1 << 40,
+                offset: 0,
+                len: chunk.len() as u64,
+                checksum: crc32fast::hash(&chunk),
+            }],
+            metadata: BTreeMap::new(),
+        };
+        let index = rkyv::to_bytes::<_, 256>(&index).unwrap();
+
+        let mut bin = MAGIC.to_vec();
+        bin.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bin.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        bin.extend_from_slice(&crc32fast::hash(&index).to_le_bytes());
+        bin.extend_from_slice(&index);
+        bin.extend_from_slice(&chunk);
+
+        let file = InstrumentFile::new(bin).unwrap();
+        assert!(matches!(file.zone(0), Err(DecodeError::Malformed(_))));
+    }
+
+    #[test]
+    fn reads_unaligned_data() {
+        let bin = encoded();
+        let mut shifted = vec![0];
+        shifted.extend_from_slice(&bin);
+
+        let file = InstrumentFile::new(&shifted[1..]).unwrap();
+        assert!(matches!(file.index, IndexBytes::Copied(_)));
+        assert_eq!(file.load().unwrap().zones[1].data.len(), 1000);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::ops::RangeInclusive;
+
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{AlignedVec, Archive, CheckBytes, Deserialize, Infallible, Serialize};
+
+use crate::{Instrument, InstrumentFile, LoopPoints};
+
+/// The bytes every encoded instrument starts with.
+pub const MAGIC: [u8; 4] = *b"ZMNI";
+
+/// The version of the encoded format. Files with a different version are
+/// rejected instead of being misinterpreted.
+///
+/// Version 1 stored the whole instrument as a single compressed archive,
+/// version 2 compresses every zone separately behind an uncompressed index.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The zstd level used when encoding instruments. Decoding speed barely
+/// depends on it, so it only trades build time for file size.
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 19;
+
+/// The magic, the version, the index length and the index's CRC-32, in that
+/// order and little-endian. The index follows right after the header, and
+/// the zone chunks follow the index.
+pub(crate) const HEADER_LEN: usize = 16;
+
+/// Everything about an instrument except for its sample data, stored as an
+/// uncompressed archive so it can be read in place.
+#[derive(Debug, Serialize, Deserialize, Archive)]
+#[archive(check_bytes)]
+pub(crate) struct Index {
+    pub(crate) name: String,
+    pub(crate) sample_rate: u32,
+    pub(crate) channels: u16,
+    pub(crate) zones: Vec<ZoneEntry>,
+    pub(crate) metadata: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Archive)]
+#[archive(check_bytes)]
+pub(crate) struct ZoneEntry {
+    pub(crate) root_note: u8,
+    pub(crate) fine_tune: f32,
+    pub(crate) low_key: u8,
+    pub(crate) high_key: u8,
+    pub(crate) low_velocity: u8,
+    pub(crate) high_velocity: u8,
+    pub(crate) loop_points: Option<LoopPoints>,
+    /// The number of interleaved samples in the zone's data.
+    pub(crate) samples: u64,
+    /// The position of the zone's chunk, relative to the end of the index.
+    pub(crate) offset: u64,
+    /// The length of the zone's chunk in bytes.
+    pub(crate) len: u64,
+    /// The CRC-32 of the zone's chunk.
+    pub(crate) checksum: u32,
+}
+
+/// An error that occurred while decoding an instrument.
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(std::io::Error),
+    /// The data ends before the header, the index or a zone does.
+    Truncated,
+    /// The data doesn't start with [`MAGIC`].
+    BadMagic,
+    /// The data was written in a format version we can't read.
+    UnsupportedVersion(u32),
+    /// The index or a zone doesn't match its checksum.
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    Decompress(std::io::Error),
+    /// The index isn't a valid archive.
+    Invalid(String),
+    /// The archive is valid, but describes an instrument that can't be played.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "I/O error: {}", e),
+            DecodeError::Truncated => write!(f, "instrument data is truncated"),
+            DecodeError::BadMagic => write!(f, "not an instrument file"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported instrument format version {}", version)
+            }
+            DecodeError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "instrument checksum mismatch: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            DecodeError::Decompress(e) => write!(f, "failed to decompress instrument: {}", e),
+            DecodeError::Invalid(reason) => write!(f, "invalid instrument data: {}", reason),
+            DecodeError::Malformed(reason) => write!(f, "malformed instrument: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Io(e) | DecodeError::Decompress(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(e: std::io::Error) -> Self {
+        DecodeError::Io(e)
+    }
+}
+
+impl Instrument {
+    /// Encodes an instrument into a binary vector with a header, an index
+    /// and one compressed chunk per zone.
+    pub fn encode(instr: Instrument) -> Vec<u8> {
+        Self::encode_with_level(instr, DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    /// Like [`Instrument::encode`], with a custom zstd compression level.
+    pub fn encode_with_level(instr: Instrument, level: i32) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        let mut zones = Vec::with_capacity(instr.zones.len());
+
+        for zone in &instr.zones {
+            let raw: Vec<u8> = zone.data.iter().flat_map(|s| s.to_le_bytes()).collect();
+            let chunk = zstd::bulk::compress(&raw, level).unwrap();
+
+            zones.push(ZoneEntry {
+                root_note: zone.root_note,
+                fine_tune: zone.fine_tune,
+                low_key: *zone.key_range.start(),
+                high_key: *zone.key_range.end(),
+                low_velocity: *zone.velocity_range.start(),
+                high_velocity: *zone.velocity_range.end(),
+                loop_points: zone.loop_points,
+                samples: zone.data.len() as u64,
+                offset: chunks.len() as u64,
+                len: chunk.len() as u64,
+                checksum: crc32fast::hash(&chunk),
+            });
+            chunks.extend_from_slice(&chunk);
+        }
+
+        let index = Index {
+            name: instr.name,
+            sample_rate: instr.sample_rate,
+            channels: instr.channels,
+            zones,
+            metadata: instr.metadata,
+        };
+        let index = rkyv::to_bytes::<_, 256>(&index).unwrap();
+
+        let mut bin = Vec::with_capacity(HEADER_LEN + index.len() + chunks.len());
+        bin.extend_from_slice(&MAGIC);
+        bin.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bin.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        bin.extend_from_slice(&crc32fast::hash(&index).to_le_bytes());
+        bin.extend_from_slice(&index);
+        bin.extend_from_slice(&chunks);
+        bin
+    }
+
+    /// Decodes and validates an encoded instrument, decompressing every zone.
+    ///
+    /// Every part of the data is checked, so corrupted or foreign input
+    /// results in an error instead of undefined behavior. Use
+    /// [`InstrumentFile`] to only decompress the zones that are needed.
+    pub fn decode(bin: &[u8]) -> Result<Instrument, DecodeError> {
+        InstrumentFile::new(bin)?.load()
+    }
+
+    /// Checks that the instrument only contains values the plugins can play.
+    pub(crate) fn validate(&self) -> Result<(), DecodeError> {
+        validate_layout(self.sample_rate, self.channels)?;
+
+        for zone in &self.zones {
+            validate_zone(
+                zone.root_note,
+                zone.fine_tune,
+                &zone.key_range,
+                &zone.velocity_range,
+                zone.loop_points,
+                zone.data.len(),
+                self.channels,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn validate_layout(sample_rate: u32, channels: u16) -> Result<(), DecodeError> {
+    if sample_rate == 0 {
+        return Err(DecodeError::Malformed("sample rate is zero"));
+    }
+    if channels == 0 {
+        return Err(DecodeError::Malformed("instrument has no channels"));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn validate_zone(
+    root_note: u8,
+    fine_tune: f32,
+    key_range: &RangeInclusive<u8>,
+    velocity_range: &RangeInclusive<u8>,
+    loop_points: Option<LoopPoints>,
+    samples: usize,
+    channels: u16,
+) -> Result<(), DecodeError> {
+    if root_note > 127 || !fine_tune.is_finite() {
+        return Err(DecodeError::Malformed("zone has an invalid tuning"));
+    }
+    if key_range.is_empty() || *key_range.end() > 127 {
+        return Err(DecodeError::Malformed("zone has an invalid key range"));
+    }
+    if velocity_range.is_empty() || *velocity_range.end() > 127 {
+        return Err(DecodeError::Malformed("zone has an invalid velocity range"));
+    }
+    if !samples.is_multiple_of(channels as usize) {
+        return Err(DecodeError::Malformed("zone data has a partial frame"));
+    }
+
+    let frames = samples / channels as usize;
+    if let Some(LoopPoints { start, end }) = loop_points {
+        if start >= end || end as usize > frames {
+            return Err(DecodeError::Malformed("zone loop is outside of the sample"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Decompresses a zstd payload into a buffer that is aligned for rkyv,
+/// failing if it would grow larger than `limit` bytes.
+pub(crate) fn decompress(payload: &[u8], limit: usize) -> Result<AlignedVec, DecodeError> {
+    let decoder = zstd::stream::Decoder::new(payload).map_err(DecodeError::Decompress)?;
+    let mut decoded = AlignedVec::new();
+    // Reading one byte past the limit tells a payload that fits exactly from
+    // one that doesn't.
+    let len = std::io::copy(&mut decoder.take(limit as u64 + 1), &mut decoded)
+        .map_err(DecodeError::Decompress)?;
+    if len > limit as u64 {
+        return Err(DecodeError::Malformed("payload is too large"));
+    }
+    Ok(decoded)
+}
+
+/// Validates an archive with bytecheck before deserializing it.
+pub(crate) fn deserialize<T>(bytes: &[u8]) -> Result<T, DecodeError>
+where
+    T: Archive,
+    T::Archived: for<'a> CheckBytes<DefaultValidator<'a>> + Deserialize<T, Infallible>,
+{
+    let archived =
+        rkyv::check_archived_root::<T>(bytes).map_err(|e| DecodeError::Invalid(e.to_string()))?;
+    Ok(archived.deserialize(&mut Infallible).unwrap())
+}
+
+pub(crate) fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::Zone;
+
+    fn instrument() -> Instrument {
+        let mut low = Zone::new(48, Arc::new(vec![0.0, 0.5, -0.5, 1.0]));
+        low.key_range = 0..=54;
+        low.fine_tune = -12.5;
+        low.loop_points = Some(LoopPoints { start: 0, end: 2 });
+        let mut high = Zone::new(60, Arc::new(vec![0.25, -0.25]));
+        high.key_range = 55..=127;
+        high.velocity_range = 64..=127;
+
+        Instrument {
+            name: "Test".to_string(),
+            sample_rate: 48000,
+            channels: 2,
+            zones: vec![low, high],
+            metadata: BTreeMap::from([("author".to_string(), "ZMANN".to_string())]),
+        }
+    }
+
+    /// A xorshift generator, so the "random" input is the same on every run.
+    fn random_bytes(state: &mut u32, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                *state ^= *state << 13;
+                *state ^= *state >> 17;
+                *state ^= *state << 5;
+                *state as u8
+            })
+            .collect()
+    }
+
+    /// Wraps an index in a header that passes every check before validation.
+    fn with_header(index: &[u8]) -> Vec<u8> {
+        let mut bin = MAGIC.to_vec();
+        bin.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bin.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        bin.extend_from_slice(&crc32fast::hash(index).to_le_bytes());
+        bin.extend_from_slice(index);
+        bin
+    }
+
+    #[test]
+    fn round_trip() {
+        let instrument = instrument();
+        let decoded = Instrument::decode(&Instrument::encode(instrument.clone())).unwrap();
+
+        assert_eq!(decoded.name, instrument.name);
+        assert_eq!(decoded.sample_rate, 48000);
+        assert_eq!(decoded.channels, 2);
+        assert_eq!(decoded.metadata, instrument.metadata);
+        for (a, b) in decoded.zones.iter().zip(&instrument.zones) {
+            assert_eq!(a.root_note, b.root_note);
+            assert_eq!(a.fine_tune, b.fine_tune);
+            assert_eq!(a.key_range, b.key_range);
+            assert_eq!(a.velocity_range, b.velocity_range);
+            assert_eq!(a.loop_points, b.loop_points);
+            assert_eq!(a.data, b.data);
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bin = Instrument::encode(instrument());
+        bin[4] = 1;
+        assert!(matches!(
+            Instrument::decode(&bin),
+            Err(DecodeError::UnsupportedVersion(1))
+        ));
+        assert!(matches!(
+            Instrument::decode(b"RIFF0000WAVEfmt "),
+            Err(DecodeError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let bin = Instrument::encode(instrument());
+        for len in 0..bin.len() {
+            assert!(Instrument::decode(&bin[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupted_data() {
+        // Both the index and the zone chunks are covered by checksums.
+        let bin = Instrument::encode(instrument());
+        for i in HEADER_LEN..bin.len() {
+            let mut corrupted = bin.clone();
+            corrupted[i] ^= 0x55;
+            assert!(matches!(
+                Instrument::decode(&corrupted),
+                Err(DecodeError::ChecksumMismatch { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_random_input() {
+        let mut state = 0x1234_5678;
+        for len in 0..512 {
+            let bytes = random_bytes(&mut state, len);
+            assert!(Instrument::decode(&bytes).is_err());
+            // With a valid header, the random bytes reach the index validation.
+            assert!(Instrument::decode(&with_header(&bytes)).is_err());
+        }
+    }
+
+    #[test]
+    fn limits_decompressed_payloads() {
+        let payload = zstd::encode_all(&[7u8; 100][..], 1).unwrap();
+        assert_eq!(decompress(&payload, 100).unwrap().as_slice(), [7; 100]);
+        assert!(matches!(
+            decompress(&payload, 99),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unplayable_instruments() {
+        let mut instrument = instrument();
+        instrument.zones[0].loop_points = Some(LoopPoints { start: 1, end: 3 });
+        assert!(matches!(
+            Instrument::decode(&Instrument::encode(instrument)),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+//! Readers for the per-plugin formats that predate [`Instrument`], so the
+//! existing sample blobs keep working.
+
+use std::sync::Arc;
+
+use rkyv::{Archive, Deserialize, Serialize};
+use rustc_hash::FxHashMap;
+
+use crate::format::{decompress, deserialize};
+use crate::{DecodeError, Instrument, LoopPoints, Zone, MAGIC};
+
+/// The sample rate all legacy blobs were recorded at.
+pub const LEGACY_SAMPLE_RATE: u32 = 44100;
+
+/// The note at which Orchestron samples play back at their original pitch.
+pub const ORCHESTRON_ROOT_NOTE: u8 = 53;
+
+// No legacy blob decompresses to more than a few megabytes, so anything far
+// beyond that is not worth allocating.
+const MAX_DECOMPRESSED_LEN: usize = 256 << 20;
+
+// One sample per note, each played at its original pitch.
+#[derive(Debug, Serialize, Deserialize, Archive)]
+#[archive(check_bytes)]
+struct BellsInstrument {
+    name: String,
+    samples: FxHashMap<u8, Vec<f32>>,
+}
+
+// A single looped sample that is repitched across the keyboard.
+#[derive(Debug, Serialize, Deserialize, Archive)]
+#[archive(check_bytes)]
+struct OrchestronInstrument {
+    name: String,
+    sample: Vec<f32>,
+}
+
+/// Decodes a Bells blob into an instrument with one zone per note.
+///
+/// These blobs have no header, but their contents are validated just like
+/// [`Instrument::decode`] does. Blobs that were already converted to the
+/// current format are passed on to [`Instrument::decode`].
+pub fn decode_bells(bin: &[u8]) -> Result<Instrument, DecodeError> {
+    if bin.starts_with(&MAGIC) {
+        return Instrument::decode(bin);
+    }
+
+    let legacy: BellsInstrument = deserialize(&decompress(bin, MAX_DECOMPRESSED_LEN)?)?;
+
+    let mut zones: Vec<Zone> = legacy
+        .samples
+        .into_iter()
+        .map(|(note, data)| Zone {
+            key_range: note..=note,
+            ..Zone::new(note, Arc::new(data))
+        })
+        .collect();
+    zones.sort_by_key(|zone| zone.root_note);
+
+    let instrument = Instrument {
+        name: legacy.name,
+        sample_rate: LEGACY_SAMPLE_RATE,
+        channels: 1,
+        zones,
+        ..Instrument::default()
+    };
+    instrument.validate()?;
+
+    Ok(instrument)
+}
+
+/// Decodes an Orchestron blob into an instrument with a single zone that
+/// covers the whole keyboard and loops the entire sample.
+///
+/// Like [`decode_bells`], this also accepts blobs in the current format.
+pub fn decode_orchestron(bin: &[u8]) -> Result<Instrument, DecodeError> {
+    if bin.starts_with(&MAGIC) {
+        return Instrument::decode(bin);
+    }
+
+    let legacy: OrchestronInstrument = deserialize(&decompress(bin, MAX_DECOMPRESSED_LEN)?)?;
+
+    let loop_points = LoopPoints {
+        start: 0,
+        end: legacy.sample.len() as u32,
+    };
+
+    let instrument = Instrument {
+        name: legacy.name,
+        sample_rate: LEGACY_SAMPLE_RATE,
+        channels: 1,
+        zones: vec![Zone {
+            loop_points: Some(loop_points),
+            ..Zone::new(ORCHESTRON_ROOT_NOTE, Arc::new(legacy.sample))
+        }],
+        ..Instrument::default()
+    };
+    instrument.validate()?;
+
+    Ok(instrument)
+}
+
+#[cfg(test)]
+mod tests {
+    use zstd::encode_all;
+
+    use super::*;
+
+    #[test]
+    fn bells_zones_are_played_at_their_own_note() {
+        let legacy = BellsInstrument {
+            name: "Brass".to_string(),
+            samples: FxHashMap::from_iter([(72, vec![0.5; 3]), (60, vec![0.25; 2])]),
+        };
+        let bin = encode_all(rkyv::to_bytes::<_, 256>(&legacy).unwrap().as_ref(), 1).unwrap();
+        let instrument = decode_bells(&bin).unwrap();
+
+        assert_eq!(instrument.name, "Brass");
+        assert_eq!(instrument.zones.len(), 2);
+        assert_eq!(instrument.zones[0].root_note, 60);
+        assert_eq!(instrument.find_zone(72, 1.0).unwrap().data.len(), 3);
+        assert!(instrument.find_zone(61, 1.0).is_none());
+    }
+
+    #[test]
+    fn orchestron_blobs_loop_the_whole_sample() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../samples/orchestron/cello"
+        );
+        let instrument = decode_orchestron(&std::fs::read(path).unwrap()).unwrap();
+        let zone = instrument.find_zone(0, 0.5).unwrap();
+
+        assert_eq!(instrument.sample_rate, LEGACY_SAMPLE_RATE);
+        assert_eq!(zone.root_note, ORCHESTRON_ROOT_NOTE);
+        assert_eq!(zone.loop_points.unwrap().end as usize, zone.data.len());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let garbage = encode_all(&b"definitely not an instrument"[..], 1).unwrap();
+        assert!(decode_bells(&garbage).is_err());
+        assert!(decode_orchestron(&garbage).is_err());
+        assert!(decode_orchestron(&[]).is_err());
+    }
+
+    #[test]
+    fn accepts_the_current_format() {
+        let instrument = Instrument {
+            name: "Converted".to_string(),
+            sample_rate: 48000,
+            channels: 1,
+            zones: vec![Zone::new(60, Arc::new(vec![0.5; 16]))],
+            ..Instrument::default()
+        };
+        let bin = Instrument::encode(instrument);
+
+        assert_eq!(decode_bells(&bin).unwrap().sample_rate, 48000);
+        assert_eq!(decode_orchestron(&bin).unwrap().name, "Converted");
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use rkyv::{Archive, Deserialize, Serialize};
+
+pub mod cache;
+mod file;
+mod format;
+pub mod legacy;
+pub mod library;
+pub mod metadata;
+
+pub use self::{file::*, format::*};
+
+/// A sampled instrument, made up of zones that each map a sample to a range
+/// of notes and velocities.
+#[derive(Clone, Debug, Default)]
+pub struct Instrument {
+    pub name: String,
+    /// The sample rate of all zones in Hz.
+    pub sample_rate: u32,
+    /// The number of interleaved channels in every zone's data.
+    pub channels: u16,
+    pub zones: Vec<Zone>,
+    /// Free-form information about the instrument, such as its author.
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// A sample and the notes and velocities it is played for.
+#[derive(Clone, Debug)]
+pub struct Zone {
+    /// The MIDI note at which the sample plays back at its original pitch.
+    pub root_note: u8,
+    /// The pitch offset from `root_note` in cents.
+    pub fine_tune: f32,
+    pub key_range: RangeInclusive<u8>,
+    pub velocity_range: RangeInclusive<u8>,
+    /// The sustain loop, or `None` if the sample is played once.
+    pub loop_points: Option<LoopPoints>,
+    /// Interleaved samples with the instrument's channel count.
+    pub data: Arc<Vec<f32>>,
+}
+
+/// A sustain loop in sample frames. `end` is exclusive, so the loop covers
+/// `start..end`.
+#[derive(Clone, Debug, Serialize, Deserialize, Archive, Copy, Eq, PartialEq)]
+#[archive(check_bytes)]
+pub struct LoopPoints {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Zone {
+    /// Creates a zone that plays `data` at its original pitch for `root_note`
+    /// and covers every note and velocity.
+    pub fn new(root_note: u8, data: Arc<Vec<f32>>) -> Self {
+        Self {
+            root_note,
+            fine_tune: 0.0,
+            key_range: 0..=127,
+            velocity_range: 0..=127,
+            loop_points: None,
+            data,
+        }
+    }
+
+    /// Returns `true` if this zone should be played for a note and a MIDI
+    /// velocity.
+    pub fn contains(&self, note: u8, velocity: u8) -> bool {
+        self.key_range.contains(&note) && self.velocity_range.contains(&velocity)
+    }
+}
+
+impl Instrument {
+    /// Returns the first zone that covers a note at the given velocity.
+    ///
+    /// `velocity` is normalized to `[0.0, 1.0]`, like in NIH-plug's note
+    /// events.
+    pub fn find_zone(&self, note: u8, velocity: f32) -> Option<&Zone> {
+        let velocity = (velocity.clamp(0.0, 1.0) * 127.0).round() as u8;
+        self.zones.iter().find(|zone| zone.contains(note, velocity))
+    }
+
+    /// Mixes every zone down to mono by averaging the channels. Loop points
+    /// are counted in frames, so they stay the same.
+    pub fn downmix(&mut self) {
+        let channels = self.channels.max(1) as usize;
+        if channels == 1 {
+            return;
+        }
+
+        for zone in &mut self.zones {
+            zone.data = Arc::new(
+                zone.data
+                    .chunks_exact(channels)
+                    .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                    .collect(),
+            );
+        }
+        self.channels = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_zones_by_key_and_velocity() {
+        let mut soft = Zone::new(60, Arc::new(vec![0.1]));
+        soft.velocity_range = 0..=63;
+        let loud = Zone::new(60, Arc::new(vec![0.9]));
+        let instrument = Instrument {
+            zones: vec![soft, loud],
+            ..Instrument::default()
+        };
+
+        assert_eq!(instrument.find_zone(60, 0.2).unwrap().data[0], 0.1);
+        assert_eq!(instrument.find_zone(60, 1.0).unwrap().data[0], 0.9);
+    }
+
+    #[test]
+    fn downmixes_to_mono() {
+        let mut zone = Zone::new(60, Arc::new(vec![1.0, 0.0, 0.5, 0.5, -1.0, 0.0]));
+        zone.loop_points = Some(LoopPoints { start: 1, end: 3 });
+        let mut instrument = Instrument {
+            channels: 2,
+            zones: vec![zone],
+            ..Instrument::default()
+        };
+
+        instrument.downmix();
+        assert_eq!(instrument.channels, 1);
+        assert_eq!(*instrument.zones[0].data, vec![0.5, 0.5, -0.5]);
+        assert_eq!(
+            instrument.zones[0].loop_points,
+            Some(LoopPoints { start: 1, end: 3 })
+        );
+    }
+}
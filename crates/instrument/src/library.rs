@@ -0,0 +1,104 @@
+//! Instrument files that users put into a library directory on disk, so the
+//! plugins aren't limited to the presets that are compiled into them.
+
+use std::path::{Path, PathBuf};
+
+use crate::{DecodeError, Instrument};
+
+/// The file extension of instrument files.
+pub const INSTRUMENT_EXTENSION: &str = "zmi";
+
+/// An instrument file in a library directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LibraryEntry {
+    /// The file name without its extension.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Returns the directory a plugin loads user instruments from:
+///
+/// - Windows: `%APPDATA%\ZMANN\<plugin>`
+/// - macOS: `~/Library/Application Support/ZMANN/<plugin>`
+/// - Linux: `$XDG_DATA_HOME/ZMANN/<plugin>`, or `~/.local/share/ZMANN/<plugin>`
+pub fn user_library_dir(plugin: &str) -> Option<PathBuf> {
+    let env = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+
+    let data_dir = if cfg!(windows) {
+        env("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env("HOME").map(|home| Path::new(&home).join("Library/Application Support"))
+    } else {
+        env("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env("HOME").map(|home| Path::new(&home).join(".local/share")))
+    };
+
+    data_dir.map(|dir| dir.join("ZMANN").join(plugin))
+}
+
+/// Lists the instrument files in a directory, sorted by name.
+///
+/// A directory that doesn't exist or can't be read is treated as empty, so
+/// plugins can call this without setting anything up first.
+pub fn scan(dir: &Path) -> Vec<LibraryEntry> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut library: Vec<LibraryEntry> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case(INSTRUMENT_EXTENSION))
+        })
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            Some(LibraryEntry { name, path })
+        })
+        .collect();
+    library.sort_by_cached_key(|entry| entry.name.to_lowercase());
+
+    library
+}
+
+/// Reads and decodes an instrument file.
+pub fn load(path: &Path) -> Result<Instrument, DecodeError> {
+    Instrument::decode(&std::fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::Zone;
+
+    #[test]
+    fn scans_and_loads_instrument_files() {
+        let dir = std::env::temp_dir().join(format!("zmann-library-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let bin = Instrument::encode(Instrument {
+            name: "Pad".to_string(),
+            sample_rate: 48000,
+            channels: 1,
+            zones: vec![Zone::new(60, Arc::new(vec![0.5; 16]))],
+            ..Instrument::default()
+        });
+        std::fs::write(dir.join("pad.zmi"), &bin).unwrap();
+        std::fs::write(dir.join("Brass.ZMI"), &bin).unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let library = scan(&dir);
+        let names: Vec<_> = library.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["Brass", "pad"]);
+        assert_eq!(load(&library[1].path).unwrap().name, "Pad");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(scan(&dir).is_empty());
+        assert!(matches!(load(&library[1].path), Err(DecodeError::Io(_))));
+    }
+}
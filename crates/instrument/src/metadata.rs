@@ -0,0 +1,81 @@
+//! Well-known keys for [`Instrument::metadata`](crate::Instrument::metadata).
+//!
+//! Importers store what they know about an instrument under these keys, so
+//! the plugins can pick it up. All values are formatted as plain numbers.
+
+use std::collections::BTreeMap;
+
+/// The attack time of the amplitude envelope in seconds.
+pub const AMP_ATTACK: &str = "amp_attack";
+/// The decay time of the amplitude envelope in seconds.
+pub const AMP_DECAY: &str = "amp_decay";
+/// The sustain level of the amplitude envelope, from 0 to 1.
+pub const AMP_SUSTAIN: &str = "amp_sustain";
+/// The release time of the amplitude envelope in seconds.
+pub const AMP_RELEASE: &str = "amp_release";
+
+/// The amplitude envelope that is stored under the `AMP_*` keys. The plugins
+/// play instruments that have one with it instead of their own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmpEnvelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl AmpEnvelope {
+    /// Reads the envelope from an instrument's metadata, or returns `None` if
+    /// any of its keys is missing or isn't a number.
+    pub fn read(metadata: &BTreeMap<String, String>) -> Option<Self> {
+        let value = |key: &str| {
+            metadata
+                .get(key)?
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite())
+        };
+
+        Some(Self {
+            attack: value(AMP_ATTACK)?,
+            decay: value(AMP_DECAY)?,
+            sustain: value(AMP_SUSTAIN)?,
+            release: value(AMP_RELEASE)?,
+        })
+    }
+
+    /// Stores the envelope in an instrument's metadata.
+    pub fn write(&self, metadata: &mut BTreeMap<String, String>) {
+        for (key, value) in [
+            (AMP_ATTACK, self.attack),
+            (AMP_DECAY, self.decay),
+            (AMP_SUSTAIN, self.sustain),
+            (AMP_RELEASE, self.release),
+        ] {
+            metadata.insert(key.to_string(), value.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amp_envelope_round_trip() {
+        let envelope = AmpEnvelope {
+            attack: 0.01,
+            decay: 0.5,
+            sustain: 0.8,
+            release: 1.25,
+        };
+        let mut metadata = BTreeMap::new();
+        assert_eq!(AmpEnvelope::read(&metadata), None);
+
+        envelope.write(&mut metadata);
+        assert_eq!(AmpEnvelope::read(&metadata), Some(envelope));
+
+        metadata.insert(AMP_DECAY.to_string(), "slow".to_string());
+        assert_eq!(AmpEnvelope::read(&metadata), None);
+    }
+}
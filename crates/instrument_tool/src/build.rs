@@ -0,0 +1,419 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use common::audio::{self, AudioFile};
+use common::resampler::{resample_interleaved, Quality};
+use instrument::{Instrument, LoopPoints, Zone};
+
+use crate::cli::{BuildArgs, ProcessArgs};
+use crate::manifest::{parse_file_name, Manifest, ZoneManifest};
+
+/// The file extensions that are picked up when scanning a directory.
+const AUDIO_EXTENSIONS: [&str; 5] = ["wav", "flac", "aif", "aiff", "ogg"];
+
+/// A decoded audio file and everything that is known about its zone so far.
+pub struct Source {
+    pub path: PathBuf,
+    pub audio: AudioFile,
+    pub root_note: u8,
+    pub fine_tune: f32,
+    pub key_range: Option<RangeInclusive<u8>>,
+    pub velocity_range: Option<RangeInclusive<u8>>,
+    /// The highest velocity from the file name, used to stack velocity
+    /// layers when there is no explicit range.
+    pub velocity_hint: Option<u8>,
+    pub loop_points: Option<LoopPoints>,
+}
+
+pub fn run(args: BuildArgs) -> Result<()> {
+    let manifest = Manifest::load(&args.input)?.unwrap_or_default();
+
+    let sources = if manifest.zones.is_empty() {
+        scan_directory(&args.input)?
+    } else {
+        manifest
+            .zones
+            .iter()
+            .map(|zone| load_manifest_zone(&args.input, zone))
+            .collect::<Result<_>>()?
+    };
+
+    if sources.is_empty() {
+        bail!("no samples found in {}", args.input.display());
+    }
+
+    let name = args
+        .name
+        .or(manifest.name)
+        .or_else(|| {
+            args.input
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default();
+
+    write_instrument(
+        sources,
+        name,
+        manifest.metadata,
+        &args.process,
+        &args.output,
+    )
+}
+
+/// Processes the sources, fills in the missing key and velocity ranges, and
+/// writes the result to an instrument file.
+pub fn write_instrument(
+    mut sources: Vec<Source>,
+    name: String,
+    metadata: BTreeMap<String, String>,
+    args: &ProcessArgs,
+    output: &Path,
+) -> Result<()> {
+    let channels = sources[0].audio.channels;
+    if let Some(source) = sources.iter().find(|s| s.audio.channels != channels) {
+        bail!(
+            "{} has {} channels, but {} has {}",
+            source.path.display(),
+            source.audio.channels,
+            sources[0].path.display(),
+            channels
+        );
+    }
+
+    // The ranges only depend on the roots and file names, so mistakes in them
+    // show up before anything is processed.
+    assign_key_ranges(&mut sources);
+    assign_velocity_ranges(&mut sources)?;
+
+    let sample_rate = args.sample_rate.unwrap_or(sources[0].audio.sample_rate);
+    for source in &mut sources {
+        resample_source(source, sample_rate);
+        if let Some(threshold_db) = args.trim {
+            trim_source(source, threshold_db);
+        }
+    }
+
+    if let Some(peak_db) = args.normalize {
+        normalize(&mut sources, peak_db);
+    }
+
+    let instrument = Instrument {
+        name,
+        sample_rate,
+        channels,
+        zones: sources
+            .into_iter()
+            .map(|source| Zone {
+                root_note: source.root_note,
+                fine_tune: source.fine_tune,
+                key_range: source.key_range.unwrap_or(0..=127),
+                velocity_range: source.velocity_range.unwrap_or(0..=127),
+                loop_points: source.loop_points,
+                data: Arc::new(source.audio.samples),
+            })
+            .collect(),
+        metadata,
+    };
+
+    let zones = instrument.zones.len();
+    let bin = Instrument::encode_with_level(instrument, args.level);
+
+    // Decoding it again catches zones the plugins would reject, such as loops
+    // that were moved past the end of a trimmed sample.
+    let instrument = Instrument::decode(&bin).context("the built instrument is invalid")?;
+
+    std::fs::write(output, &bin)
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    println!(
+        "Wrote '{}' with {} zones at {} Hz ({} bytes) to {}",
+        instrument.name,
+        zones,
+        sample_rate,
+        bin.len(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// Loads every audio file in a directory whose name contains a note.
+fn scan_directory(directory: &Path) -> Result<Vec<Source>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(directory)
+        .with_context(|| format!("failed to read {}", directory.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+
+    let mut sources = Vec::new();
+    for path in paths {
+        let is_audio = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        if !is_audio {
+            continue;
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let parsed = parse_file_name(&stem);
+        let audio = load_audio(&path)?;
+
+        // Files without a note in their name can still carry one in their
+        // sampler metadata.
+        let Some(root_note) = parsed.map(|(note, _)| note).or(audio.metadata.root_note) else {
+            eprintln!(
+                "Skipping {}: no note in its name or metadata",
+                path.display()
+            );
+            continue;
+        };
+
+        let mut source = source_from_metadata(path, audio, root_note);
+        source.velocity_hint = parsed.and_then(|(_, velocity)| velocity);
+        sources.push(source);
+    }
+
+    Ok(sources)
+}
+
+fn load_manifest_zone(directory: &Path, zone: &ZoneManifest) -> Result<Source> {
+    let path = directory.join(&zone.file);
+    let audio = load_audio(&path)?;
+
+    let root_note = match &zone.root {
+        Some(note) => note.to_midi()?,
+        None => audio
+            .metadata
+            .root_note
+            .or_else(|| {
+                let stem = path.file_stem()?.to_string_lossy();
+                parse_file_name(&stem).map(|(note, _)| note)
+            })
+            .ok_or(anyhow!("{} has no root note", path.display()))?,
+    };
+
+    let mut source = source_from_metadata(path, audio, root_note);
+    if let Some(fine_tune) = zone.fine_tune {
+        source.fine_tune = fine_tune;
+    }
+    if let Some([low, high]) = &zone.keys {
+        source.key_range = Some(low.to_midi()?..=high.to_midi()?);
+    }
+    if let Some([low, high]) = zone.velocities {
+        source.velocity_range = Some(low..=high);
+    }
+    if let Some([start, end]) = zone.loop_points {
+        source.loop_points = Some(LoopPoints { start, end });
+    }
+
+    Ok(source)
+}
+
+pub fn load_audio(path: &Path) -> Result<AudioFile> {
+    audio::load(path.to_path_buf()).with_context(|| format!("failed to load {}", path.display()))
+}
+
+/// Creates a source with the root note and everything else the file's
+/// sampler metadata provides.
+fn source_from_metadata(path: PathBuf, audio: AudioFile, root_note: u8) -> Source {
+    let metadata = &audio.metadata;
+    let fine_tune = if metadata.root_note == Some(root_note) {
+        metadata.fine_tune
+    } else {
+        0.0
+    };
+
+    Source {
+        fine_tune,
+        key_range: metadata.key_range.clone(),
+        velocity_range: metadata.velocity_range.clone(),
+        velocity_hint: None,
+        loop_points: metadata.loops.first().map(|l| LoopPoints {
+            start: l.start,
+            end: l.end,
+        }),
+        root_note,
+        path,
+        audio,
+    }
+}
+
+fn resample_source(source: &mut Source, sample_rate: u32) {
+    let audio = &mut source.audio;
+    if audio.sample_rate == sample_rate {
+        return;
+    }
+
+    let ratio = sample_rate as f64 / audio.sample_rate as f64;
+    audio.samples = resample_interleaved(
+        &audio.samples,
+        audio.channels as usize,
+        audio.sample_rate as f32,
+        sample_rate as f32,
+        Quality::Best,
+    );
+    audio.sample_rate = sample_rate;
+
+    let frames = audio.frames() as u32;
+    if let Some(loop_points) = &mut source.loop_points {
+        loop_points.start = ((loop_points.start as f64 * ratio).round() as u32).min(frames);
+        loop_points.end = ((loop_points.end as f64 * ratio).round() as u32).min(frames);
+    }
+}
+
+/// Removes leading and trailing frames where every channel is below the
+/// threshold, without cutting into the loop.
+fn trim_source(source: &mut Source, threshold_db: f32) {
+    let threshold = 10.0_f32.powf(threshold_db / 20.0);
+    let channels = source.audio.channels as usize;
+    let is_audible = |frame: &[f32]| frame.iter().any(|s| s.abs() >= threshold);
+
+    let frames: Vec<&[f32]> = source.audio.samples.chunks_exact(channels).collect();
+    let Some(mut start) = frames.iter().position(|f| is_audible(f)) else {
+        eprintln!("Not trimming {}: it is silent", source.path.display());
+        return;
+    };
+    let mut end = frames.iter().rposition(|f| is_audible(f)).unwrap() + 1;
+
+    if let Some(loop_points) = &mut source.loop_points {
+        start = start.min(loop_points.start as usize);
+        end = end.max(loop_points.end as usize);
+        loop_points.start -= start as u32;
+        loop_points.end -= start as u32;
+    }
+
+    source.audio.samples = source.audio.samples[start * channels..end * channels].to_vec();
+}
+
+/// Applies the same gain to every source, so their relative levels stay
+/// intact.
+fn normalize(sources: &mut [Source], peak_db: f32) {
+    let peak = sources
+        .iter()
+        .flat_map(|source| &source.audio.samples)
+        .fold(0.0_f32, |peak, s| peak.max(s.abs()));
+    if peak == 0.0 {
+        return;
+    }
+
+    let gain = 10.0_f32.powf(peak_db / 20.0) / peak;
+    for sample in sources.iter_mut().flat_map(|s| &mut s.audio.samples) {
+        *sample *= gain;
+    }
+}
+
+/// Spreads the zones without an explicit key range across the keyboard, so
+/// every note plays the closest root note.
+fn assign_key_ranges(sources: &mut [Source]) {
+    let mut roots: Vec<u8> = sources
+        .iter()
+        .filter(|source| source.key_range.is_none())
+        .map(|source| source.root_note)
+        .collect();
+    roots.sort_unstable();
+    roots.dedup();
+
+    for source in sources.iter_mut().filter(|s| s.key_range.is_none()) {
+        let i = roots.binary_search(&source.root_note).unwrap();
+        let low = match i {
+            0 => 0,
+            _ => (roots[i - 1] + source.root_note) / 2 + 1,
+        };
+        let high = match roots.get(i + 1) {
+            Some(next) => (source.root_note + next) / 2,
+            None => 127,
+        };
+        source.key_range = Some(low..=high);
+    }
+}
+
+/// Stacks the velocity layers of zones that share a root note, using the
+/// velocities from their file names as upper bounds. Fails if two of them
+/// would cover the same velocities.
+fn assign_velocity_ranges(sources: &mut [Source]) -> Result<()> {
+    let mut layers: BTreeMap<u8, Vec<&mut Source>> = BTreeMap::new();
+    for source in sources.iter_mut().filter(|s| s.velocity_range.is_none()) {
+        layers.entry(source.root_note).or_default().push(source);
+    }
+
+    for layer in layers.values_mut() {
+        layer.sort_by_key(|source| source.velocity_hint.unwrap_or(127));
+        if let Some(pair) = layer.windows(2).find(|pair| {
+            pair[0].velocity_hint.unwrap_or(127) == pair[1].velocity_hint.unwrap_or(127)
+        }) {
+            bail!(
+                "{} and {} both play note {} up to velocity {}, name them after \
+                 different velocities",
+                pair[0].path.display(),
+                pair[1].path.display(),
+                pair[0].root_note,
+                pair[0].velocity_hint.unwrap_or(127)
+            );
+        }
+
+        let mut low = 0;
+        let top = layer.len() - 1;
+        for (i, source) in layer.iter_mut().enumerate() {
+            // The loudest layer also covers everything above its velocity.
+            let high = match i == top {
+                true => 127,
+                false => source.velocity_hint.unwrap_or(127).max(low),
+            };
+            source.velocity_range = Some(low..=high);
+            low = high.saturating_add(1);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(name: &str, velocity_hint: Option<u8>) -> Source {
+        Source {
+            path: PathBuf::from(name),
+            audio: AudioFile::default(),
+            root_note: 60,
+            fine_tune: 0.0,
+            key_range: None,
+            velocity_range: None,
+            velocity_hint,
+            loop_points: None,
+        }
+    }
+
+    #[test]
+    fn stacks_velocity_layers() {
+        let mut sources = vec![
+            source("C4_f.wav", None),
+            source("C4_p.wav", Some(40)),
+            source("C4_mf.wav", Some(90)),
+        ];
+        assign_velocity_ranges(&mut sources).unwrap();
+
+        assert_eq!(sources[0].velocity_range, Some(91..=127));
+        assert_eq!(sources[1].velocity_range, Some(0..=40));
+        assert_eq!(sources[2].velocity_range, Some(41..=90));
+    }
+
+    #[test]
+    fn rejects_layers_with_the_same_velocity() {
+        let mut sources = vec![source("C4_a.wav", None), source("C4_b.wav", None)];
+        let error = assign_velocity_ranges(&mut sources)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("C4_a.wav") && error.contains("C4_b.wav"),
+            "{error}"
+        );
+
+        let mut sources = vec![source("C4_a.wav", Some(127)), source("C4_b.wav", None)];
+        assert!(assign_velocity_ranges(&mut sources).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use common::wav::BitDepth;
+use instrument::DEFAULT_COMPRESSION_LEVEL;
+
+#[derive(Parser)]
+#[command(name = "instrument")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+#[derive(Args)]
+pub struct BuildArgs {
+    /// A directory of audio files. If it contains an `instrument.toml`
+    /// manifest, that describes the zones. Otherwise they are derived from
+    /// file names like `C4.wav`, `60.wav` or `F#3_v100.wav`.
+    pub input: PathBuf,
+
+    /// Where to write the instrument file.
+    pub output: PathBuf,
+
+    /// The instrument's name. Defaults to the manifest's name or the name of
+    /// the input directory.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// An SFZ file or a SoundFont 2 bank. The samples an SFZ file refers to
+    /// are resolved relative to it.
+    pub input: PathBuf,
+
+    /// Where to write the instrument file.
+    pub output: PathBuf,
+
+    /// The instrument's name. Defaults to the name of the SoundFont preset or
+    /// the input file.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// The SoundFont preset to convert, either by name or as `BANK:PROGRAM`.
+    /// Can be left out if the bank only has a single preset.
+    #[arg(short, long)]
+    pub preset: Option<String>,
+
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+/// How the samples are processed before they are written to an instrument
+/// file.
+#[derive(Args)]
+pub struct ProcessArgs {
+    /// Resample every sample to this rate in Hz. Defaults to the rate of the
+    /// first sample.
+    #[arg(short = 'r', long)]
+    pub sample_rate: Option<u32>,
+
+    /// Remove silence below this level in dB from the start and end of every
+    /// sample.
+    #[arg(
+        long,
+        value_name = "DB",
+        num_args = 0..=1,
+        default_missing_value = "-60",
+        allow_negative_numbers = true
+    )]
+    pub trim: Option<f32>,
+
+    /// Scale all samples by the same amount so the loudest peak reaches this
+    /// level in dB.
+    #[arg(
+        long,
+        value_name = "DB",
+        num_args = 0..=1,
+        default_missing_value = "0",
+        allow_negative_numbers = true
+    )]
+    pub normalize: Option<f32>,
+
+    /// The zstd compression level.
+    #[arg(
+        short,
+        long,
+        default_value_t = DEFAULT_COMPRESSION_LEVEL,
+        value_parser = clap::value_parser!(i32).range(1..=22)
+    )]
+    pub level: i32,
+}
+
+#[derive(Args)]
+pub struct InspectArgs {
+    /// An instrument file, either in the current format or one of the legacy
+    /// Bells and Orchestron formats.
+    pub file: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ExtractArgs {
+    /// An instrument file, either in the current format or one of the legacy
+    /// Bells and Orchestron formats.
+    pub file: PathBuf,
+
+    /// The directory to write the WAV files and an `instrument.toml` manifest
+    /// to, which `build` can turn back into the same instrument.
+    pub output: PathBuf,
+
+    /// The sample format of the WAV files.
+    #[arg(short, long, value_enum, default_value_t = SampleFormat::Float)]
+    pub format: SampleFormat,
+
+    /// Add dither when writing an integer sample format.
+    #[arg(long)]
+    pub dither: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SampleFormat {
+    #[value(name = "16")]
+    Int16,
+    #[value(name = "24")]
+    Int24,
+    #[value(name = "32")]
+    Int32,
+    Float,
+}
+
+impl From<SampleFormat> for BitDepth {
+    fn from(format: SampleFormat) -> Self {
+        match format {
+            SampleFormat::Int16 => BitDepth::Int16,
+            SampleFormat::Int24 => BitDepth::Int24,
+            SampleFormat::Int32 => BitDepth::Int32,
+            SampleFormat::Float => BitDepth::Float32,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Build an instrument file from a directory of audio files.
+    Build(BuildArgs),
+    /// Convert an SFZ file or a SoundFont 2 preset into an instrument file.
+    Import(ImportArgs),
+    /// Print an instrument's header and a summary of every zone.
+    Inspect(InspectArgs),
+    /// Write every zone of an instrument to a WAV file.
+    Extract(ExtractArgs),
+}
@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use common::audio::{AudioFile, SampleLoop, SampleMetadata};
+use common::wav::{self, ExportOptions};
+
+use crate::cli::ExtractArgs;
+use crate::inspect::read_instrument;
+use crate::manifest::{note_name, Manifest, Note, ZoneManifest};
+
+pub fn run(args: ExtractArgs) -> Result<()> {
+    let (instrument, _) = read_instrument(&args.file)?;
+
+    std::fs::create_dir_all(&args.output)
+        .with_context(|| format!("failed to create {}", args.output.display()))?;
+
+    let options = ExportOptions {
+        bit_depth: args.format.into(),
+        dither: args.dither,
+    };
+
+    let mut zones = Vec::with_capacity(instrument.zones.len());
+    for (i, zone) in instrument.zones.iter().enumerate() {
+        // The index keeps names unique when zones share a root and velocity,
+        // and comes last so `build` still finds the note in the name.
+        let file = format!(
+            "{}_v{}_{}.wav",
+            note_name(zone.root_note),
+            zone.velocity_range.end(),
+            i
+        );
+        let path = args.output.join(&file);
+
+        let audio = AudioFile {
+            sample_rate: instrument.sample_rate,
+            channels: instrument.channels,
+            samples: zone.data.to_vec(),
+            metadata: SampleMetadata {
+                root_note: Some(zone.root_note),
+                fine_tune: zone.fine_tune,
+                key_range: Some(zone.key_range.clone()),
+                velocity_range: Some(zone.velocity_range.clone()),
+                loops: zone
+                    .loop_points
+                    .iter()
+                    .map(|l| SampleLoop {
+                        start: l.start,
+                        end: l.end,
+                        ..SampleLoop::default()
+                    })
+                    .collect(),
+                ..SampleMetadata::default()
+            },
+        };
+        wav::save(&path, &audio, options)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        // The manifest repeats the metadata so the instrument can be rebuilt
+        // exactly, even after the files were edited in a tool that drops it.
+        zones.push(ZoneManifest {
+            file: file.into(),
+            root: Some(Note::Name(note_name(zone.root_note))),
+            fine_tune: (zone.fine_tune != 0.0).then_some(zone.fine_tune),
+            keys: Some([
+                Note::Name(note_name(*zone.key_range.start())),
+                Note::Name(note_name(*zone.key_range.end())),
+            ]),
+            velocities: Some([*zone.velocity_range.start(), *zone.velocity_range.end()]),
+            loop_points: zone.loop_points.as_ref().map(|l| [l.start, l.end]),
+        });
+    }
+
+    let manifest = Manifest {
+        name: Some(instrument.name.clone()),
+        metadata: instrument.metadata.clone(),
+        zones,
+    };
+    manifest.save(&args.output)?;
+
+    println!(
+        "Extracted {} zones of '{}' to {}",
+        instrument.zones.len(),
+        instrument.name,
+        args.output.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use instrument::metadata::AmpEnvelope;
+
+use crate::build::{write_instrument, Source};
+use crate::cli::ImportArgs;
+use crate::{sf2, sfz};
+
+/// The zones an importer found in a file.
+#[derive(Default)]
+pub struct Import {
+    /// The name stored in the file, if it has one.
+    pub name: Option<String>,
+    pub sources: Vec<Source>,
+    /// The amplitude envelope of every source, if it has one.
+    pub envelopes: Vec<Option<AmpEnvelope>>,
+}
+
+pub fn run(args: ImportArgs) -> Result<()> {
+    let extension = args
+        .input
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    let import = match extension.as_deref() {
+        Some("sfz") => sfz::import(&args.input)?,
+        Some("sf2") => sf2::import(&args.input, args.preset.as_deref())?,
+        _ => bail!(
+            "{} is neither an SFZ file nor a SoundFont 2 bank",
+            args.input.display()
+        ),
+    };
+
+    if import.sources.is_empty() {
+        bail!("no playable zones found in {}", args.input.display());
+    }
+
+    // Zones don't have their own envelopes, so the instrument gets the one of
+    // the first zone.
+    let mut instrument_metadata = BTreeMap::new();
+    let envelopes = &import.envelopes;
+    if envelopes.iter().any(|e| *e != envelopes[0]) {
+        eprintln!("The zones have different amp envelopes, using the one of the first zone");
+    }
+    if let Some(envelope) = envelopes[0] {
+        envelope.write(&mut instrument_metadata);
+    }
+
+    let name = args
+        .name
+        .or(import.name)
+        .or_else(|| {
+            args.input
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default();
+
+    write_instrument(
+        import.sources,
+        name,
+        instrument_metadata,
+        &args.process,
+        &args.output,
+    )
+}
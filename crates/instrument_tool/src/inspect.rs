@@ -0,0 +1,122 @@
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use instrument::{legacy, Instrument, Zone, MAGIC};
+
+use crate::cli::InspectArgs;
+use crate::manifest::note_name;
+
+/// The format an instrument file was stored in.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum Format {
+    Current(u32),
+    LegacyBells,
+    LegacyOrchestron,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Current(version) => write!(f, "version {}", version),
+            Format::LegacyBells => write!(f, "legacy Bells (unversioned)"),
+            Format::LegacyOrchestron => write!(f, "legacy Orchestron (unversioned)"),
+        }
+    }
+}
+
+/// Reads an instrument file in any format the plugins can load.
+pub fn read_instrument(path: &Path) -> Result<(Instrument, Format)> {
+    let bin = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    if bin.starts_with(&MAGIC) {
+        let version = bin
+            .get(4..8)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or(anyhow!("{} is truncated", path.display()))?;
+        let instrument = Instrument::decode(&bin)
+            .with_context(|| format!("failed to decode {}", path.display()))?;
+        return Ok((instrument, Format::Current(version)));
+    }
+
+    // The legacy blobs have no header, so the only way to tell them apart is
+    // to try both.
+    legacy::decode_orchestron(&bin)
+        .map(|instrument| (instrument, Format::LegacyOrchestron))
+        .or_else(|_| legacy::decode_bells(&bin).map(|i| (i, Format::LegacyBells)))
+        .map_err(|_| {
+            anyhow!(
+                "{} is neither an instrument file nor a legacy blob",
+                path.display()
+            )
+        })
+}
+
+pub fn run(args: InspectArgs) -> Result<()> {
+    let (instrument, format) = read_instrument(&args.file)?;
+
+    println!("Name:        {}", instrument.name);
+    println!("Format:      {}", format);
+    println!("Sample rate: {} Hz", instrument.sample_rate);
+    println!("Channels:    {}", instrument.channels);
+    for (key, value) in &instrument.metadata {
+        println!("Metadata:    {} = {}", key, value);
+    }
+    println!("Zones:       {}", instrument.zones.len());
+    println!();
+
+    println!(
+        "{:>4}  {:<10} {:>6}  {:<11} {:<8} {:>18}  {:>9} {:>9}  Loop",
+        "#", "Root", "Tune", "Keys", "Vel", "Length", "Peak", "RMS"
+    );
+    for (i, zone) in instrument.zones.iter().enumerate() {
+        let frames = zone.data.len() / instrument.channels as usize;
+        let (peak, rms) = levels(zone);
+        let loop_points = match &zone.loop_points {
+            Some(l) => format!("{}..{} ({} frames)", l.start, l.end, l.end - l.start),
+            None => "-".to_string(),
+        };
+
+        println!(
+            "{:>4}  {:<10} {:>+6.1}  {:<11} {:<8} {:>18}  {:>9} {:>9}  {}",
+            i,
+            format!("{} ({})", note_name(zone.root_note), zone.root_note),
+            zone.fine_tune,
+            format!(
+                "{}..{}",
+                note_name(*zone.key_range.start()),
+                note_name(*zone.key_range.end())
+            ),
+            format!(
+                "{}..{}",
+                zone.velocity_range.start(),
+                zone.velocity_range.end()
+            ),
+            format!(
+                "{} ({:.2} s)",
+                frames,
+                frames as f64 / instrument.sample_rate as f64
+            ),
+            format_db(peak),
+            format_db(rms),
+            loop_points
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the peak and RMS level of a zone's data across all channels.
+fn levels(zone: &Zone) -> (f32, f32) {
+    let peak = zone.data.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+    let sum: f64 = zone.data.iter().map(|&s| s as f64 * s as f64).sum();
+    let rms = (sum / zone.data.len().max(1) as f64).sqrt() as f32;
+    (peak, rms)
+}
+
+fn format_db(level: f32) -> String {
+    match level {
+        0.0 => "-inf dB".to_string(),
+        _ => format!("{:.1} dB", 20.0 * level.log10()),
+    }
+}
@@ -0,0 +1,24 @@
+use anyhow::Result;
+use clap::Parser;
+
+mod build;
+mod cli;
+mod extract;
+mod import;
+mod inspect;
+mod manifest;
+mod sf2;
+mod sfz;
+
+use cli::{Cli, Commands};
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Commands::Build(args) => build::run(args),
+        Commands::Import(args) => import::run(args),
+        Commands::Inspect(args) => inspect::run(args),
+        Commands::Extract(args) => extract::run(args),
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// The file name of the manifest in an instrument's source directory.
+pub const MANIFEST_FILE_NAME: &str = "instrument.toml";
+
+/// Describes how the audio files in a directory make up an instrument.
+///
+/// ```toml
+/// name = "Brass"
+///
+/// [metadata]
+/// author = "ZMANN"
+///
+/// [[zone]]
+/// file = "brass_low.wav"
+/// root = "C3"
+/// keys = ["C0", 59]
+/// velocities = [0, 100]
+/// loop = [1200, 48000]
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// If this is empty, the zones are derived from the file names instead.
+    #[serde(default, rename = "zone")]
+    pub zones: Vec<ZoneManifest>,
+}
+
+/// A single zone. Everything except for the file is optional and falls back
+/// to the sampler metadata in the file, and then to the file name.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneManifest {
+    /// The audio file, relative to the manifest.
+    pub file: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<Note>,
+    /// The pitch offset from `root` in cents.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fine_tune: Option<f32>,
+    /// The lowest and highest note this zone is played for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<[Note; 2]>,
+    /// The lowest and highest velocity this zone is played for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub velocities: Option<[u8; 2]>,
+    /// The start and exclusive end of the sustain loop in frames.
+    #[serde(rename = "loop", skip_serializing_if = "Option::is_none")]
+    pub loop_points: Option<[u32; 2]>,
+}
+
+/// A MIDI note, either as a number or as a name like `C4` or `F#3`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Note {
+    Number(u8),
+    Name(String),
+}
+
+impl Note {
+    pub fn to_midi(&self) -> Result<u8> {
+        match self {
+            Note::Number(note) if *note <= 127 => Ok(*note),
+            Note::Number(note) => Err(anyhow!("note {} is out of range", note)),
+            Note::Name(name) => {
+                parse_note_name(name).ok_or(anyhow!("invalid note name '{}'", name))
+            }
+        }
+    }
+}
+
+impl Manifest {
+    /// Reads the manifest from a directory, if it has one.
+    pub fn load(directory: &Path) -> Result<Option<Manifest>> {
+        let path = directory.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let manifest = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let manifest = toml::from_str(&manifest)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    /// Writes the manifest to a directory.
+    pub fn save(&self, directory: &Path) -> Result<()> {
+        let path = directory.join(MANIFEST_FILE_NAME);
+        let manifest = toml::to_string(self).context("failed to serialize the manifest")?;
+        std::fs::write(&path, manifest)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// Parses a note name like `C4`, `Eb2` or `F#-1`, where `C4` is MIDI note 60.
+pub fn parse_note_name(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let semitone: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next()? {
+        '#' => (1, &rest[1..]),
+        'b' => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let octave: i32 = octave.parse().ok()?;
+
+    u8::try_from((octave + 1) * 12 + semitone + accidental)
+        .ok()
+        .filter(|note| *note <= 127)
+}
+
+/// Formats a MIDI note as a name like `C4` or `F#3`, the inverse of
+/// [`parse_note_name`].
+pub fn note_name(note: u8) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    format!("{}{}", NAMES[note as usize % 12], note as i32 / 12 - 1)
+}
+
+/// Extracts the note and the optional velocity from a file name like
+/// `Piano_C4_v100`. The velocity is the highest one the sample is meant for.
+pub fn parse_file_name(stem: &str) -> Option<(u8, Option<u8>)> {
+    let mut note = None;
+    let mut velocity = None;
+
+    for token in stem.split(['_', '-', ' ']) {
+        if let Some(value) = token
+            .strip_prefix(['v', 'V'])
+            .and_then(|v| v.parse::<u8>().ok())
+        {
+            velocity = Some(value.min(127));
+        } else if note.is_none() {
+            note = token
+                .parse::<u8>()
+                .ok()
+                .filter(|note| *note <= 127)
+                .or_else(|| parse_note_name(token));
+        }
+    }
+
+    note.map(|note| (note, velocity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_names() {
+        assert_eq!(parse_note_name("C4"), Some(60));
+        assert_eq!(parse_note_name("A4"), Some(69));
+        assert_eq!(parse_note_name("F#3"), Some(54));
+        assert_eq!(parse_note_name("Eb2"), Some(39));
+        assert_eq!(parse_note_name("C-1"), Some(0));
+        assert_eq!(parse_note_name("G9"), Some(127));
+        assert_eq!(parse_note_name("G#9"), None);
+        assert_eq!(parse_note_name("H2"), None);
+
+        assert_eq!(note_name(60), "C4");
+        assert_eq!(note_name(54), "F#3");
+        assert_eq!(note_name(0), "C-1");
+        for note in 0..=127 {
+            assert_eq!(parse_note_name(&note_name(note)), Some(note));
+        }
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(parse_file_name("C4_v100"), Some((60, Some(100))));
+        assert_eq!(parse_file_name("Brass_72"), Some((72, None)));
+        assert_eq!(parse_file_name("Piano F#3 v64"), Some((54, Some(64))));
+        assert_eq!(parse_file_name("release_noise"), None);
+    }
+}
@@ -0,0 +1,894 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use common::audio::AudioFile;
+use instrument::metadata::AmpEnvelope;
+use instrument::LoopPoints;
+
+use crate::build::Source;
+use crate::import::Import;
+
+// Generator operators from the SoundFont 2.04 specification.
+const START_OFFSET: u16 = 0;
+const END_OFFSET: u16 = 1;
+const LOOP_START_OFFSET: u16 = 2;
+const LOOP_END_OFFSET: u16 = 3;
+const START_COARSE_OFFSET: u16 = 4;
+const END_COARSE_OFFSET: u16 = 12;
+const ATTACK_VOL_ENV: u16 = 34;
+const DECAY_VOL_ENV: u16 = 36;
+const SUSTAIN_VOL_ENV: u16 = 37;
+const RELEASE_VOL_ENV: u16 = 38;
+const INSTRUMENT: u16 = 41;
+const KEY_RANGE: u16 = 43;
+const VEL_RANGE: u16 = 44;
+const LOOP_START_COARSE_OFFSET: u16 = 45;
+const INITIAL_ATTENUATION: u16 = 48;
+const LOOP_END_COARSE_OFFSET: u16 = 50;
+const COARSE_TUNE: u16 = 51;
+const FINE_TUNE: u16 = 52;
+const SAMPLE_ID: u16 = 53;
+const SAMPLE_MODES: u16 = 54;
+const OVERRIDING_ROOT_KEY: u16 = 58;
+
+/// The generators that are turned into zones. Everything else is reported
+/// as unsupported.
+const SUPPORTED_GENERATORS: [u16; 21] = [
+    START_OFFSET,
+    END_OFFSET,
+    LOOP_START_OFFSET,
+    LOOP_END_OFFSET,
+    START_COARSE_OFFSET,
+    END_COARSE_OFFSET,
+    ATTACK_VOL_ENV,
+    DECAY_VOL_ENV,
+    SUSTAIN_VOL_ENV,
+    RELEASE_VOL_ENV,
+    INSTRUMENT,
+    KEY_RANGE,
+    VEL_RANGE,
+    LOOP_START_COARSE_OFFSET,
+    INITIAL_ATTENUATION,
+    LOOP_END_COARSE_OFFSET,
+    COARSE_TUNE,
+    FINE_TUNE,
+    SAMPLE_ID,
+    SAMPLE_MODES,
+    OVERRIDING_ROOT_KEY,
+];
+
+/// The names of the generator operators, for reporting.
+const GENERATOR_NAMES: [&str; 61] = [
+    "startAddrsOffset",
+    "endAddrsOffset",
+    "startloopAddrsOffset",
+    "endloopAddrsOffset",
+    "startAddrsCoarseOffset",
+    "modLfoToPitch",
+    "vibLfoToPitch",
+    "modEnvToPitch",
+    "initialFilterFc",
+    "initialFilterQ",
+    "modLfoToFilterFc",
+    "modEnvToFilterFc",
+    "endAddrsCoarseOffset",
+    "modLfoToVolume",
+    "unused1",
+    "chorusEffectsSend",
+    "reverbEffectsSend",
+    "pan",
+    "unused2",
+    "unused3",
+    "unused4",
+    "delayModLFO",
+    "freqModLFO",
+    "delayVibLFO",
+    "freqVibLFO",
+    "delayModEnv",
+    "attackModEnv",
+    "holdModEnv",
+    "decayModEnv",
+    "sustainModEnv",
+    "releaseModEnv",
+    "keynumToModEnvHold",
+    "keynumToModEnvDecay",
+    "delayVolEnv",
+    "attackVolEnv",
+    "holdVolEnv",
+    "decayVolEnv",
+    "sustainVolEnv",
+    "releaseVolEnv",
+    "keynumToVolEnvHold",
+    "keynumToVolEnvDecay",
+    "instrument",
+    "reserved1",
+    "keyRange",
+    "velRange",
+    "startloopAddrsCoarseOffset",
+    "keynum",
+    "velocity",
+    "initialAttenuation",
+    "reserved2",
+    "endloopAddrsCoarseOffset",
+    "coarseTune",
+    "fineTune",
+    "sampleID",
+    "sampleModes",
+    "reserved3",
+    "scaleTuning",
+    "exclusiveClass",
+    "overridingRootKey",
+    "unused5",
+    "endOper",
+];
+
+// Sample types. Linked samples are the two halves of a stereo sample.
+const RIGHT_SAMPLE: u16 = 2;
+const LEFT_SAMPLE: u16 = 4;
+const ROM_SAMPLE: u16 = 0x8000;
+
+/// A parsed SoundFont 2 bank. The sample data is read from the file's bytes
+/// when a preset is converted.
+#[derive(Debug)]
+struct SoundFont<'a> {
+    name: String,
+    /// Sorted by bank and program.
+    presets: Vec<Preset>,
+    instruments: Vec<SfInstrument>,
+    samples: Vec<SampleHeader>,
+    /// 16-bit sample points.
+    smpl: &'a [u8],
+    /// The optional lower 8 bits of 24-bit sample points.
+    sm24: Option<&'a [u8]>,
+}
+
+#[derive(Debug)]
+struct Preset {
+    name: String,
+    bank: u16,
+    program: u16,
+    global: Generators,
+    zones: Vec<Generators>,
+}
+
+/// An instrument in a SoundFont bank, which presets are made of.
+#[derive(Debug)]
+struct SfInstrument {
+    global: Generators,
+    zones: Vec<Generators>,
+}
+
+/// The generators of a zone by operator, with their raw amounts.
+#[derive(Clone, Debug, Default)]
+struct Generators {
+    amounts: BTreeMap<u16, u16>,
+    /// The number of modulators the zone has, which are all ignored.
+    modulators: usize,
+}
+
+#[derive(Debug)]
+struct SampleHeader {
+    name: String,
+    start: u32,
+    end: u32,
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate: u32,
+    original_pitch: u8,
+    /// The pitch offset in cents to apply when playing the sample.
+    pitch_correction: i8,
+    link: u16,
+    kind: u16,
+}
+
+/// A zone of a preset before the halves of stereo samples are paired up.
+struct Region {
+    sample: usize,
+    header: (u16, u16),
+    source: Source,
+    envelope: Option<AmpEnvelope>,
+}
+
+/// Reads a SoundFont 2 bank and converts one of its presets.
+pub fn import(path: &Path, preset: Option<&str>) -> Result<Import> {
+    let bin = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let soundfont =
+        SoundFont::parse(&bin).with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let preset = soundfont.find_preset(preset)?;
+    println!(
+        "Converting preset {}:{} '{}' of '{}'",
+        preset.bank, preset.program, preset.name, soundfont.name
+    );
+    soundfont.convert(preset)
+}
+
+impl<'a> SoundFont<'a> {
+    fn parse(bin: &'a [u8]) -> Result<Self> {
+        let body = match chunks(bin)?.first() {
+            Some((id, body)) if id == b"RIFF" && body.starts_with(b"sfbk") => &body[4..],
+            _ => bail!("not a SoundFont 2 bank"),
+        };
+
+        // The sub-chunk IDs are unique across the INFO, sdta and pdta lists.
+        let mut sub_chunks = HashMap::new();
+        for (id, list) in chunks(body)? {
+            if &id == b"LIST" && list.len() >= 4 {
+                sub_chunks.extend(chunks(&list[4..])?);
+            }
+        }
+        let records = |id: &[u8; 4], size: usize| -> Result<Vec<&'a [u8]>> {
+            let data: &[u8] = sub_chunks.get(id).ok_or(anyhow!(
+                "the {} chunk is missing",
+                String::from_utf8_lossy(id)
+            ))?;
+            if data.len() % size != 0 || data.len() < size {
+                bail!("the {} chunk is malformed", String::from_utf8_lossy(id));
+            }
+            Ok(data.chunks_exact(size).collect())
+        };
+
+        let smpl: &[u8] = sub_chunks
+            .get(b"smpl")
+            .ok_or(anyhow!("there is no sample data"))?;
+        let sm24 = sub_chunks
+            .get(b"sm24")
+            .copied()
+            .filter(|sm24| sm24.len() >= smpl.len() / 2);
+
+        let phdr = records(b"phdr", 38)?;
+        let preset_zones = zones(
+            &phdr.iter().map(|r| read_u16(r, 24)).collect::<Vec<_>>(),
+            &records(b"pbag", 4)?,
+            &records(b"pgen", 4)?,
+            INSTRUMENT,
+        )?;
+        let mut presets: Vec<Preset> = phdr
+            .iter()
+            .zip(preset_zones)
+            .map(|(record, (global, zones))| Preset {
+                name: read_name(record),
+                program: read_u16(record, 20) as u16,
+                bank: read_u16(record, 22) as u16,
+                global,
+                zones,
+            })
+            .collect();
+        presets.sort_by_key(|preset| (preset.bank, preset.program));
+
+        let inst = records(b"inst", 22)?;
+        let instruments = zones(
+            &inst.iter().map(|r| read_u16(r, 20)).collect::<Vec<_>>(),
+            &records(b"ibag", 4)?,
+            &records(b"igen", 4)?,
+            SAMPLE_ID,
+        )?
+        .into_iter()
+        .map(|(global, zones)| SfInstrument { global, zones })
+        .collect();
+
+        let shdr = records(b"shdr", 46)?;
+        let samples = shdr[..shdr.len() - 1]
+            .iter()
+            .map(|record| SampleHeader {
+                name: read_name(record),
+                start: read_u32(record, 20),
+                end: read_u32(record, 24),
+                loop_start: read_u32(record, 28),
+                loop_end: read_u32(record, 32),
+                sample_rate: read_u32(record, 36),
+                original_pitch: record[40],
+                pitch_correction: record[41] as i8,
+                link: read_u16(record, 42) as u16,
+                kind: read_u16(record, 44) as u16,
+            })
+            .collect();
+
+        let name = sub_chunks.get(b"INAM").map_or(String::new(), |name| {
+            String::from_utf8_lossy(name)
+                .trim_end_matches('\0')
+                .to_string()
+        });
+
+        Ok(SoundFont {
+            name,
+            presets,
+            instruments,
+            samples,
+            smpl,
+            sm24,
+        })
+    }
+
+    /// Finds a preset by name or `BANK:PROGRAM`. Without a query, the bank
+    /// must have a single preset.
+    fn find_preset(&self, query: Option<&str>) -> Result<&Preset> {
+        let preset = match query {
+            Some(query) => self.presets.iter().find(|preset| {
+                format!("{}:{}", preset.bank, preset.program) == query
+                    || preset.name.eq_ignore_ascii_case(query)
+            }),
+            None if self.presets.len() == 1 => self.presets.first(),
+            None => None,
+        };
+
+        preset.ok_or_else(|| {
+            let presets: String = self
+                .presets
+                .iter()
+                .map(|p| format!("\n  {}:{} {}", p.bank, p.program, p.name))
+                .collect();
+            match query {
+                Some(query) => anyhow!("there is no preset '{}', choose one of:{}", query, presets),
+                None => anyhow!("choose one of these presets with --preset:{}", presets),
+            }
+        })
+    }
+
+    fn convert(&self, preset: &Preset) -> Result<Import> {
+        let mut regions = Vec::new();
+        let mut unsupported: BTreeMap<u16, usize> = BTreeMap::new();
+        let mut modulators = 0;
+
+        for preset_zone in &preset.zones {
+            let preset_layers = [preset_zone, &preset.global];
+            let instrument = self
+                .instruments
+                .get(preset_zone.amounts[&INSTRUMENT] as usize)
+                .ok_or(anyhow!(
+                    "preset '{}' has an invalid instrument",
+                    preset.name
+                ))?;
+
+            for zone in &instrument.zones {
+                let layers = [zone, &instrument.global];
+                let Some(region) = self.convert_zone(layers, preset_layers)? else {
+                    continue;
+                };
+
+                let mut generators: Vec<u16> = layers
+                    .iter()
+                    .chain(&preset_layers)
+                    .flat_map(|g| g.amounts.keys().copied())
+                    .filter(|op| !SUPPORTED_GENERATORS.contains(op))
+                    .collect();
+                generators.sort_unstable();
+                generators.dedup();
+                for op in generators {
+                    *unsupported.entry(op).or_default() += 1;
+                }
+                modulators += layers
+                    .iter()
+                    .chain(&preset_layers)
+                    .map(|g| g.modulators)
+                    .sum::<usize>();
+
+                regions.push(region);
+            }
+        }
+
+        for (op, count) in unsupported {
+            let name = GENERATOR_NAMES
+                .get(op as usize)
+                .copied()
+                .unwrap_or("unknown");
+            eprintln!(
+                "Ignored unsupported generator {} ({}), used by {} zone(s)",
+                name, op, count
+            );
+        }
+        if modulators > 0 {
+            eprintln!("Ignored {} modulator(s)", modulators);
+        }
+
+        let mut import = pair_stereo_samples(regions);
+        import.name = Some(preset.name.clone());
+        Ok(import)
+    }
+
+    /// Turns an instrument zone into a region, or returns `None` if it can't
+    /// be played. Both slices of generators are ordered from the zone to its
+    /// global zone.
+    fn convert_zone(
+        &self,
+        layers: [&Generators; 2],
+        preset_layers: [&Generators; 2],
+    ) -> Result<Option<Region>> {
+        let index = layers[0].amounts[&SAMPLE_ID] as usize;
+        let header = self.samples.get(index).ok_or(anyhow!(
+            "a zone refers to sample {}, which doesn't exist",
+            index
+        ))?;
+        if header.kind & ROM_SAMPLE != 0 || header.sample_rate == 0 {
+            eprintln!("Skipping {}: it's stored in a ROM", header.name);
+            return Ok(None);
+        }
+
+        // Preset ranges narrow down the instrument's.
+        let intersect = |op| {
+            let a = range(layers, op);
+            let b = range(preset_layers, op);
+            let range = *a.start().max(b.start())..=*a.end().min(b.end());
+            (!range.is_empty()).then_some(range)
+        };
+        let (Some(key_range), Some(velocity_range)) = (intersect(KEY_RANGE), intersect(VEL_RANGE))
+        else {
+            return Ok(None);
+        };
+
+        let offset =
+            |fine, coarse| signed(layers, fine) as i64 + 32768 * signed(layers, coarse) as i64;
+        let points = (self.smpl.len() / 2) as i64;
+        let start =
+            (header.start as i64 + offset(START_OFFSET, START_COARSE_OFFSET)).clamp(0, points);
+        let end = (header.end as i64 + offset(END_OFFSET, END_COARSE_OFFSET)).clamp(start, points);
+        if start == end {
+            eprintln!("Skipping {}: it's empty", header.name);
+            return Ok(None);
+        }
+
+        // Modes 1 and 3 loop, where 3 would play the rest of the sample on
+        // release.
+        let loop_points = match lookup(layers, SAMPLE_MODES).unwrap_or(0) & 3 {
+            1 | 3 => {
+                let loop_start = header.loop_start as i64
+                    + offset(LOOP_START_OFFSET, LOOP_START_COARSE_OFFSET)
+                    - start;
+                let loop_end = header.loop_end as i64
+                    + offset(LOOP_END_OFFSET, LOOP_END_COARSE_OFFSET)
+                    - start;
+                if 0 <= loop_start && loop_start < loop_end && loop_end <= end - start {
+                    Some(LoopPoints {
+                        start: loop_start as u32,
+                        end: loop_end as u32,
+                    })
+                } else {
+                    eprintln!(
+                        "Ignoring the loop of {}: it's outside of the sample",
+                        header.name
+                    );
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        let root = match lookup(layers, OVERRIDING_ROOT_KEY).map(|key| key as i16) {
+            Some(key @ 0..=127) => key as i32,
+            _ if header.original_pitch <= 127 => header.original_pitch as i32,
+            _ => 60,
+        };
+        // Preset generators are added to the instrument's. The tuning raises
+        // the pitch the sample is played at, so it moves the root down.
+        let root = root - signed(layers, COARSE_TUNE) - signed(preset_layers, COARSE_TUNE);
+        let Some(root_note) = u8::try_from(root).ok().filter(|root| *root <= 127) else {
+            eprintln!(
+                "Skipping {}: the tuned root note {} is out of range",
+                header.name, root
+            );
+            return Ok(None);
+        };
+        let fine_tune = signed(layers, FINE_TUNE)
+            + signed(preset_layers, FINE_TUNE)
+            + header.pitch_correction as i32;
+
+        // The attenuation is in centibels.
+        let attenuation =
+            signed(layers, INITIAL_ATTENUATION) + signed(preset_layers, INITIAL_ATTENUATION);
+        let gain = 10.0_f32.powf(-attenuation.max(0) as f32 / 200.0);
+        let samples = self
+            .sample_data(start as usize..end as usize)
+            .into_iter()
+            .map(|sample| sample * gain)
+            .collect();
+
+        Ok(Some(Region {
+            sample: index,
+            header: (header.kind, header.link),
+            source: Source {
+                path: PathBuf::from(&header.name),
+                audio: AudioFile {
+                    sample_rate: header.sample_rate,
+                    channels: 1,
+                    samples,
+                    ..AudioFile::default()
+                },
+                root_note,
+                fine_tune: -fine_tune as f32,
+                key_range: Some(key_range),
+                velocity_range: Some(velocity_range),
+                velocity_hint: None,
+                loop_points,
+            },
+            envelope: envelope(layers, preset_layers),
+        }))
+    }
+
+    fn sample_data(&self, points: Range<usize>) -> Vec<f32> {
+        let point = |i: usize| i16::from_le_bytes([self.smpl[i * 2], self.smpl[i * 2 + 1]]);
+        match self.sm24 {
+            Some(sm24) => points
+                .map(|i| ((point(i) as i32) << 8 | sm24[i] as i32) as f32 / 8_388_608.0)
+                .collect(),
+            None => points.map(|i| point(i) as f32 / 32_768.0).collect(),
+        }
+    }
+}
+
+/// Combines the halves of stereo samples, so every source has the same
+/// number of channels. If there are none, the sources stay mono.
+fn pair_stereo_samples(regions: Vec<Region>) -> Import {
+    let is_stereo = |region: &Region| matches!(region.header.0, LEFT_SAMPLE | RIGHT_SAMPLE);
+    if !regions.iter().any(is_stereo) {
+        return Import {
+            envelopes: regions.iter().map(|region| region.envelope).collect(),
+            sources: regions.into_iter().map(|region| region.source).collect(),
+            ..Import::default()
+        };
+    }
+
+    let mut import = Import::default();
+    let mut regions: Vec<Option<Region>> = regions.into_iter().map(Some).collect();
+    for i in 0..regions.len() {
+        let Some(region) = regions[i].take() else {
+            continue;
+        };
+
+        // The other half covers the same notes.
+        let partner = regions.iter().position(|other| {
+            other.as_ref().is_some_and(|other| {
+                is_stereo(&region)
+                    && other.sample == region.header.1 as usize
+                    && other.source.key_range == region.source.key_range
+                    && other.source.velocity_range == region.source.velocity_range
+                    && other.source.audio.sample_rate == region.source.audio.sample_rate
+            })
+        });
+        let (mut left, right) = match partner.and_then(|j| regions[j].take()) {
+            Some(other) if region.header.0 == RIGHT_SAMPLE => (other, Some(region)),
+            Some(other) => (region, Some(other)),
+            // Mono samples and unpaired halves play on both channels.
+            None => (region, None),
+        };
+
+        let samples = {
+            let left = &left.source.audio.samples;
+            let right = right.as_ref().map_or(left, |r| &r.source.audio.samples);
+            left.iter()
+                .zip(right)
+                .flat_map(|(&l, &r)| [l, r])
+                .collect::<Vec<_>>()
+        };
+        let frames = (samples.len() / 2) as u32;
+        left.source.audio.samples = samples;
+        left.source.audio.channels = 2;
+        left.source.loop_points = left.source.loop_points.filter(|l| l.end <= frames);
+
+        import.sources.push(left.source);
+        import.envelopes.push(left.envelope);
+    }
+
+    import
+}
+
+/// Reads the volume envelope, or returns `None` if none of its generators
+/// are set.
+fn envelope(layers: [&Generators; 2], preset_layers: [&Generators; 2]) -> Option<AmpEnvelope> {
+    let ops = [
+        ATTACK_VOL_ENV,
+        DECAY_VOL_ENV,
+        SUSTAIN_VOL_ENV,
+        RELEASE_VOL_ENV,
+    ];
+    if !ops
+        .iter()
+        .any(|&op| lookup(layers, op).or(lookup(preset_layers, op)).is_some())
+    {
+        return None;
+    }
+
+    // Times are in timecents and default to -12000, about 1 ms.
+    let seconds = |op| {
+        let timecents = lookup(layers, op).map_or(-12000, |amount| amount as i16 as i32)
+            + signed(preset_layers, op);
+        2.0_f32.powf(timecents as f32 / 1200.0)
+    };
+    // The sustain level is an attenuation in centibels.
+    let attenuation = signed(layers, SUSTAIN_VOL_ENV) + signed(preset_layers, SUSTAIN_VOL_ENV);
+
+    Some(AmpEnvelope {
+        attack: seconds(ATTACK_VOL_ENV),
+        decay: seconds(DECAY_VOL_ENV),
+        sustain: 10.0_f32.powf(-attenuation.clamp(0, 1440) as f32 / 200.0),
+        release: seconds(RELEASE_VOL_ENV),
+    })
+}
+
+/// Finds a generator in the first layer that has it.
+fn lookup(layers: [&Generators; 2], op: u16) -> Option<u16> {
+    layers.iter().find_map(|g| g.amounts.get(&op).copied())
+}
+
+fn signed(layers: [&Generators; 2], op: u16) -> i32 {
+    lookup(layers, op).map_or(0, |amount| amount as i16 as i32)
+}
+
+/// Reads a key or velocity range, which is stored as a low and a high byte.
+fn range(layers: [&Generators; 2], op: u16) -> RangeInclusive<u8> {
+    match lookup(layers, op) {
+        Some(amount) => (amount as u8).min(127)..=((amount >> 8) as u8).min(127),
+        None => 0..=127,
+    }
+}
+
+/// Reads the zones of every preset or instrument, given the index of its
+/// first bag. The last header is the terminal record, and so are the last
+/// bag and generator. The first zone is a global zone if it doesn't end with
+/// the `terminal` generator.
+fn zones(
+    first_bags: &[usize],
+    bags: &[&[u8]],
+    generators: &[&[u8]],
+    terminal: u16,
+) -> Result<Vec<(Generators, Vec<Generators>)>> {
+    first_bags
+        .windows(2)
+        .map(|pair| {
+            let (start, end) = (pair[0], pair[1]);
+            if start > end || end >= bags.len() {
+                bail!("the zones are malformed");
+            }
+
+            let mut zones: Vec<Generators> = (start..end)
+                .map(|bag| {
+                    let (gen_start, gen_end) = (read_u16(bags[bag], 0), read_u16(bags[bag + 1], 0));
+                    let (mod_start, mod_end) = (read_u16(bags[bag], 2), read_u16(bags[bag + 1], 2));
+                    let records = generators
+                        .get(gen_start..gen_end)
+                        .ok_or(anyhow!("the generators are malformed"))?;
+
+                    Ok(Generators {
+                        amounts: records
+                            .iter()
+                            .map(|record| (read_u16(record, 0) as u16, read_u16(record, 2) as u16))
+                            .collect(),
+                        modulators: mod_end.saturating_sub(mod_start),
+                    })
+                })
+                .collect::<Result<_>>()?;
+
+            let global = match zones.first() {
+                Some(zone) if !zone.amounts.contains_key(&terminal) => zones.remove(0),
+                _ => Generators::default(),
+            };
+            // Other zones without the terminal generator are ignored.
+            zones.retain(|zone| zone.amounts.contains_key(&terminal));
+
+            Ok((global, zones))
+        })
+        .collect()
+}
+
+/// Splits RIFF data into its chunks.
+fn chunks(mut data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut chunks = Vec::new();
+    while data.len() >= 8 {
+        let id: [u8; 4] = data[0..4].try_into().unwrap();
+        let size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        let body = 8_usize
+            .checked_add(size)
+            .and_then(|end| data.get(8..end))
+            .ok_or(anyhow!(
+                "the {} chunk is truncated",
+                String::from_utf8_lossy(&id)
+            ))?;
+        chunks.push((id, body));
+        // Chunks are padded to an even size.
+        let padded = 8_usize.saturating_add(size).saturating_add(size % 2);
+        data = data.get(padded..).unwrap_or(&[]);
+    }
+
+    Ok(chunks)
+}
+
+/// Reads a fixed-size name, which is padded with zeros.
+fn read_name(record: &[u8]) -> String {
+    let name = &record[..20];
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).trim().to_string()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn list(kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"LIST", &[&kind[..], &chunks.concat()].concat())
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(20, 0);
+        bytes
+    }
+
+    /// Encodes headers with their zones, and returns the header records
+    /// without their names, the bags and the generators.
+    fn encode_zones(zones: &[Vec<Vec<(u16, u16)>>]) -> (Vec<u16>, Vec<u8>, Vec<u8>) {
+        let (mut first_bags, mut bags, mut generators) = (Vec::new(), Vec::new(), Vec::new());
+        let mut bag_count = 0;
+        let mut generator_count = 0;
+        for header in zones.iter().chain([&Vec::new()]) {
+            first_bags.push(bag_count);
+            for zone in header {
+                bags.extend([generator_count, 0].map(u16::to_le_bytes).concat());
+                bag_count += 1;
+                for (op, amount) in zone {
+                    generators.extend([*op, *amount].map(u16::to_le_bytes).concat());
+                    generator_count += 1;
+                }
+            }
+        }
+        bags.extend([generator_count, 0].map(u16::to_le_bytes).concat());
+        generators.extend([0; 4]);
+        (first_bags, bags, generators)
+    }
+
+    fn soundfont() -> Vec<u8> {
+        let smpl: Vec<u8> = (0..30_i16).flat_map(|i| (i * 100).to_le_bytes()).collect();
+
+        // Name, start, end, loop start, loop end, rate, pitch, correction,
+        // link and type.
+        let samples = [
+            ("Mono", 0, 10, 2, 8, 22050, 60, 0, 0, 1),
+            ("Left", 10, 20, 0, 0, 44100, 72, -5, 2, LEFT_SAMPLE),
+            ("Right", 20, 30, 0, 0, 44100, 72, -5, 1, RIGHT_SAMPLE),
+            ("EOS", 0, 0, 0, 0, 0, 0, 0, 0, 0),
+        ];
+        let shdr: Vec<u8> = samples
+            .iter()
+            .flat_map(|&(n, start, end, ls, le, rate, pitch, corr, link, kind)| {
+                [
+                    name(n),
+                    [start, end, ls, le, rate].map(u32::to_le_bytes).concat(),
+                    vec![pitch, corr as u8],
+                    [link, kind].map(u16::to_le_bytes).concat(),
+                ]
+                .concat()
+            })
+            .collect();
+
+        let (inst_bags, ibag, igen) = encode_zones(&[
+            vec![
+                vec![(ATTACK_VOL_ENV, (-1200_i16) as u16), (SAMPLE_MODES, 1)],
+                vec![(KEY_RANGE, 59 << 8), (SAMPLE_ID, 0)],
+                vec![(KEY_RANGE, 60 | 127 << 8), (17, 100), (SAMPLE_ID, 0)],
+            ],
+            vec![vec![(SAMPLE_ID, 1)], vec![(SAMPLE_ID, 2)]],
+        ]);
+        let inst: Vec<u8> = ["Keys", "Stereo", "EOI"]
+            .iter()
+            .zip(inst_bags)
+            .flat_map(|(n, bag)| [name(n), bag.to_le_bytes().to_vec()].concat())
+            .collect();
+
+        let (preset_bags, pbag, pgen) = encode_zones(&[
+            vec![vec![(INSTRUMENT, 1)]],
+            vec![vec![
+                (KEY_RANGE, 48 | 72 << 8),
+                (COARSE_TUNE, 1),
+                (INSTRUMENT, 0),
+            ]],
+        ]);
+        let phdr: Vec<u8> = [("Pad", 5, 1), ("Piano", 0, 0), ("EOP", 0, 0)]
+            .iter()
+            .zip(preset_bags)
+            .flat_map(|(&(n, program, bank), bag)| {
+                [
+                    name(n),
+                    [program, bank, bag].map(u16::to_le_bytes).concat(),
+                    vec![0; 12],
+                ]
+                .concat()
+            })
+            .collect();
+
+        let body = [
+            b"sfbk".to_vec(),
+            list(b"INFO", &[chunk(b"INAM", b"Test Bank\0")]),
+            list(b"sdta", &[chunk(b"smpl", &smpl)]),
+            list(
+                b"pdta",
+                &[
+                    chunk(b"phdr", &phdr),
+                    chunk(b"pbag", &pbag),
+                    chunk(b"pmod", &[0; 10]),
+                    chunk(b"pgen", &pgen),
+                    chunk(b"inst", &inst),
+                    chunk(b"ibag", &ibag),
+                    chunk(b"imod", &[0; 10]),
+                    chunk(b"igen", &igen),
+                    chunk(b"shdr", &shdr),
+                ],
+            ),
+        ]
+        .concat();
+        chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn reads_presets() {
+        let bin = soundfont();
+        let soundfont = SoundFont::parse(&bin).unwrap();
+        assert_eq!(soundfont.name, "Test Bank");
+        assert_eq!(soundfont.presets[0].name, "Piano");
+        assert_eq!(soundfont.presets[1].name, "Pad");
+
+        assert_eq!(soundfont.find_preset(Some("1:5")).unwrap().name, "Pad");
+        assert_eq!(soundfont.find_preset(Some("piano")).unwrap().name, "Piano");
+        assert!(soundfont.find_preset(Some("Organ")).is_err());
+        assert!(soundfont.find_preset(None).is_err());
+    }
+
+    #[test]
+    fn converts_zones() {
+        let bin = soundfont();
+        let soundfont = SoundFont::parse(&bin).unwrap();
+        let import = soundfont.convert(&soundfont.presets[0]).unwrap();
+        assert_eq!(import.name.as_deref(), Some("Piano"));
+
+        // The preset's key range cuts both zones, and its tuning moves the
+        // root down.
+        let ranges: Vec<_> = import.sources.iter().map(|s| s.key_range.clone()).collect();
+        assert_eq!(ranges, [Some(48..=59), Some(60..=72)]);
+
+        let source = &import.sources[0];
+        assert_eq!(source.root_note, 59);
+        assert_eq!(source.audio.sample_rate, 22050);
+        assert_eq!(source.audio.samples.len(), 10);
+        assert_eq!(source.audio.samples[1], 100.0 / 32768.0);
+        assert_eq!(source.loop_points, Some(LoopPoints { start: 2, end: 8 }));
+
+        let envelope = import.envelopes[0].unwrap();
+        assert_eq!(envelope.attack, 0.5);
+        assert_eq!(envelope.sustain, 1.0);
+    }
+
+    #[test]
+    fn pairs_stereo_samples() {
+        let bin = soundfont();
+        let soundfont = SoundFont::parse(&bin).unwrap();
+        let import = soundfont.convert(&soundfont.presets[1]).unwrap();
+        assert_eq!(import.sources.len(), 1);
+
+        let source = &import.sources[0];
+        assert_eq!(source.audio.channels, 2);
+        assert_eq!(source.root_note, 72);
+        assert_eq!(source.fine_tune, 5.0);
+        assert_eq!(source.loop_points, None);
+        assert_eq!(
+            source.audio.samples[..4],
+            [1000.0, 2000.0, 1100.0, 2100.0].map(|s| s / 32768.0)
+        );
+    }
+}
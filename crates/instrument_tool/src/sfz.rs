@@ -0,0 +1,535 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use common::audio::AudioFile;
+use instrument::metadata::AmpEnvelope;
+use instrument::LoopPoints;
+
+use crate::build::{load_audio, Source};
+use crate::import::Import;
+use crate::manifest::parse_note_name;
+
+/// The opcodes that are turned into zones. Everything else is reported as
+/// unsupported.
+const SUPPORTED_OPCODES: &[&str] = &[
+    "sample",
+    "default_path",
+    "note_offset",
+    "octave_offset",
+    "key",
+    "lokey",
+    "hikey",
+    "pitch_keycenter",
+    "lovel",
+    "hivel",
+    "tune",
+    "transpose",
+    "volume",
+    "offset",
+    "end",
+    "trigger",
+    "loop_mode",
+    "loop_start",
+    "loop_end",
+    "ampeg_attack",
+    "ampeg_decay",
+    "ampeg_sustain",
+    "ampeg_release",
+];
+
+/// Old names of opcodes that are still found in many files.
+const ALIASES: [(&str, &str); 3] = [
+    ("loopmode", "loop_mode"),
+    ("loopstart", "loop_start"),
+    ("loopend", "loop_end"),
+];
+
+/// How deeply `#include` directives can be nested, which catches cycles.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// The opcodes of a region, including the ones it inherits.
+type Region = BTreeMap<String, String>;
+
+/// A header like `<region>` and the opcodes that follow it.
+#[derive(Debug, PartialEq)]
+struct Header {
+    name: String,
+    opcodes: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+struct SfzFile {
+    /// Every region merged with its `<control>`, `<global>`, `<master>` and
+    /// `<group>` opcodes.
+    regions: Vec<Region>,
+    /// Headers like `<curve>` or `<effect>` that are skipped, and how often
+    /// they occur.
+    ignored_headers: BTreeMap<String, usize>,
+}
+
+/// Reads an SFZ file and the samples its regions refer to.
+pub fn import(path: &Path) -> Result<Import> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let text = preprocess(&text, directory, &mut Vec::new(), 0)?;
+    let sfz = SfzFile::parse(&text);
+
+    let mut import = Import::default();
+    let mut unsupported: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut cache = HashMap::new();
+    for (i, region) in sfz.regions.iter().enumerate() {
+        for opcode in region.keys() {
+            if !SUPPORTED_OPCODES.contains(&opcode.as_str()) {
+                *unsupported.entry(opcode).or_default() += 1;
+            }
+        }
+
+        let source = convert_region(region, directory, &mut cache)
+            .with_context(|| format!("failed to import region {}", i + 1))?;
+        if let Some(source) = source {
+            import.sources.push(source);
+            import.envelopes.push(envelope(region)?);
+        }
+    }
+
+    for (header, count) in &sfz.ignored_headers {
+        eprintln!(
+            "Ignored unsupported header <{}>, found {} time(s)",
+            header, count
+        );
+    }
+    for (opcode, count) in &unsupported {
+        eprintln!(
+            "Ignored unsupported opcode {}, used by {} region(s)",
+            opcode, count
+        );
+    }
+
+    Ok(import)
+}
+
+impl SfzFile {
+    /// Parses preprocessed SFZ text and resolves the opcode inheritance.
+    fn parse(text: &str) -> Self {
+        let mut sfz = SfzFile::default();
+        let mut control = Vec::new();
+        let mut global = Vec::new();
+        let mut master = Vec::new();
+        let mut group = Vec::new();
+
+        for header in headers(text) {
+            match header.name.as_str() {
+                "control" => control.extend(header.opcodes),
+                "global" => {
+                    global = header.opcodes;
+                    master.clear();
+                    group.clear();
+                }
+                "master" => {
+                    master = header.opcodes;
+                    group.clear();
+                }
+                "group" => group = header.opcodes,
+                "region" => sfz.regions.push(
+                    control
+                        .iter()
+                        .chain(&global)
+                        .chain(&master)
+                        .chain(&group)
+                        .chain(&header.opcodes)
+                        .map(|(opcode, value)| {
+                            let opcode = ALIASES
+                                .iter()
+                                .find(|(alias, _)| alias == opcode)
+                                .map_or(opcode.as_str(), |(_, name)| name);
+                            (opcode.to_string(), value.clone())
+                        })
+                        .collect(),
+                ),
+                other => *sfz.ignored_headers.entry(other.to_string()).or_default() += 1,
+            }
+        }
+
+        sfz
+    }
+}
+
+/// Removes comments and expands `#define` and `#include` directives.
+/// Included files are resolved relative to `directory`, the directory of the
+/// top-level file.
+fn preprocess(
+    text: &str,
+    directory: &Path,
+    defines: &mut Vec<(String, String)>,
+    depth: usize,
+) -> Result<String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        bail!("#include directives are nested too deeply, they might form a cycle");
+    }
+
+    let mut output = String::with_capacity(text.len());
+    for line in strip_comments(text).lines() {
+        let trimmed = line.trim_start();
+
+        if let Some(rest) = trimmed.strip_prefix("#define") {
+            let rest = rest.trim_start();
+            let (name, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let value = expand_defines(value.trim(), defines);
+            defines.push((name.to_string(), value));
+            // Longer names go first, so `$NOTE` doesn't replace a part of
+            // `$NOTE_LOW`.
+            defines.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        } else if let Some(rest) = trimmed.strip_prefix("#include") {
+            let file = expand_defines(rest.trim().trim_matches('"'), defines);
+            let path = directory.join(normalize_path(&file));
+            let included = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            output.push_str(&preprocess(&included, directory, defines, depth + 1)?);
+        } else {
+            output.push_str(&expand_defines(line, defines));
+            output.push('\n');
+        }
+    }
+
+    Ok(output)
+}
+
+/// Removes `//` and `/* */` comments, keeping the line breaks.
+fn strip_comments(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('/') {
+        output.push_str(&rest[..start]);
+        let comment = &rest[start..];
+
+        if comment.starts_with("//") {
+            rest = &comment[comment.find('\n').unwrap_or(comment.len())..];
+        } else if comment.starts_with("/*") {
+            let end = comment.find("*/").map_or(comment.len(), |end| end + 2);
+            output.extend(comment[..end].chars().filter(|&c| c == '\n'));
+            rest = &comment[end..];
+        } else {
+            output.push('/');
+            rest = &comment[1..];
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+fn expand_defines(line: &str, defines: &[(String, String)]) -> String {
+    let mut line = line.to_string();
+    for (name, value) in defines {
+        line = line.replace(name.as_str(), value);
+    }
+    line
+}
+
+/// Splits preprocessed text into headers and their opcodes.
+///
+/// Values end at the next opcode, header or line break, so sample paths can
+/// contain spaces. Opcodes before the first header are ignored.
+fn headers(text: &str) -> Vec<Header> {
+    let mut headers: Vec<Header> = Vec::new();
+
+    for line in text.lines() {
+        let line = line.replace('<', " <").replace('>', "> ");
+        // Whether the following words still belong to the last value.
+        let mut in_value = false;
+
+        for word in line.split_whitespace() {
+            if let Some(name) = word.strip_prefix('<').and_then(|w| w.strip_suffix('>')) {
+                headers.push(Header {
+                    name: name.to_string(),
+                    opcodes: Vec::new(),
+                });
+                in_value = false;
+                continue;
+            }
+
+            let Some(header) = headers.last_mut() else {
+                continue;
+            };
+            match word.split_once('=') {
+                Some((opcode, value)) if is_opcode_name(opcode) => {
+                    header.opcodes.push((opcode.to_string(), value.to_string()));
+                    in_value = true;
+                }
+                _ if in_value => {
+                    let value = &mut header.opcodes.last_mut().unwrap().1;
+                    value.push(' ');
+                    value.push_str(word);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    headers
+}
+
+fn is_opcode_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// SFZ files are often written on Windows.
+fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// Turns a region into a source, or returns `None` if it can't be played as
+/// a zone.
+fn convert_region(
+    region: &Region,
+    directory: &Path,
+    cache: &mut HashMap<PathBuf, AudioFile>,
+) -> Result<Option<Source>> {
+    if let Some(trigger) = region.get("trigger").filter(|t| *t != "attack") {
+        eprintln!("Skipping a region with trigger={}", trigger);
+        return Ok(None);
+    }
+    let Some(sample) = region.get("sample") else {
+        eprintln!("Skipping a region without a sample");
+        return Ok(None);
+    };
+    if sample.starts_with('*') {
+        eprintln!("Skipping a region with the generator {}", sample);
+        return Ok(None);
+    }
+
+    let default_path = region.get("default_path").map_or("", String::as_str);
+    let path = directory
+        .join(normalize_path(default_path))
+        .join(normalize_path(sample));
+    if !cache.contains_key(&path) {
+        let audio = load_audio(&path)?;
+        cache.insert(path.clone(), audio);
+    }
+    let mut audio = cache[&path].clone();
+
+    let shift = parse::<i32>(region, "note_offset")?.unwrap_or(0)
+        + 12 * parse::<i32>(region, "octave_offset")?.unwrap_or(0);
+    let note = |opcode: &str| {
+        region
+            .get(opcode)
+            .map(|value| parse_key(value, shift))
+            .transpose()
+            .with_context(|| format!("invalid value for {}", opcode))
+    };
+
+    let key = note("key")?;
+    let low_key = note("lokey")?.or(key).unwrap_or(0);
+    let high_key = note("hikey")?.or(key).unwrap_or(127);
+    let low_velocity = parse::<u8>(region, "lovel")?.unwrap_or(0).min(127);
+    let high_velocity = parse::<u8>(region, "hivel")?.unwrap_or(127).min(127);
+    if low_key > high_key || low_velocity > high_velocity {
+        eprintln!(
+            "Skipping {}: its key or velocity range is empty",
+            path.display()
+        );
+        return Ok(None);
+    }
+
+    let keycenter = match region.get("pitch_keycenter").map(String::as_str) {
+        Some("sample") => audio.metadata.root_note.unwrap_or(60),
+        Some(_) => note("pitch_keycenter")?.unwrap(),
+        None => key.unwrap_or(60),
+    };
+    let root = keycenter as i32 - parse::<i32>(region, "transpose")?.unwrap_or(0);
+    let root_note = u8::try_from(root)
+        .ok()
+        .filter(|root| *root <= 127)
+        .ok_or(anyhow!("the transposed root note {} is out of range", root))?;
+
+    // `end` is the last frame that is played, and a negative one disables the
+    // region.
+    let frames = audio.frames();
+    let offset = parse::<usize>(region, "offset")?.unwrap_or(0).min(frames);
+    let end = match parse::<i64>(region, "end")? {
+        Some(end) if end < 0 => {
+            eprintln!("Skipping {}: the region is disabled", path.display());
+            return Ok(None);
+        }
+        Some(end) => (end as usize).saturating_add(1).min(frames),
+        None => frames,
+    };
+    if offset >= end {
+        eprintln!("Skipping {}: the played part is empty", path.display());
+        return Ok(None);
+    }
+
+    // Without a loop mode, samples loop if the file has a loop.
+    let file_loop = audio.metadata.loops.first();
+    let looping = match region.get("loop_mode").map(String::as_str) {
+        None => file_loop.is_some(),
+        Some("no_loop" | "one_shot") => false,
+        Some("loop_continuous" | "loop_sustain") => true,
+        Some(mode) => bail!("unknown loop_mode '{}'", mode),
+    };
+    let loop_points = if looping {
+        // Like `end`, `loop_end` is inclusive.
+        let loop_start = parse::<usize>(region, "loop_start")?
+            .or(file_loop.map(|l| l.start as usize))
+            .unwrap_or(offset);
+        let loop_end = parse::<usize>(region, "loop_end")?
+            .map(|loop_end| loop_end.saturating_add(1))
+            .or(file_loop.map(|l| l.end as usize))
+            .unwrap_or(end);
+
+        let start = loop_start.clamp(offset, end) - offset;
+        let end = loop_end.clamp(offset, end) - offset;
+        if start < end {
+            Some(LoopPoints {
+                start: start as u32,
+                end: end as u32,
+            })
+        } else {
+            eprintln!(
+                "Ignoring the loop of {}: it's outside of the sample",
+                path.display()
+            );
+            None
+        }
+    } else {
+        None
+    };
+
+    let gain = 10.0_f32.powf(parse::<f32>(region, "volume")?.unwrap_or(0.0) / 20.0);
+    let channels = audio.channels as usize;
+    audio.samples = audio.samples[offset * channels..end * channels]
+        .iter()
+        .map(|sample| sample * gain)
+        .collect();
+
+    Ok(Some(Source {
+        path,
+        audio,
+        root_note,
+        // `tune` raises the pitch the sample is played at, while the fine
+        // tune is the offset of the recorded pitch from the root note.
+        fine_tune: parse::<f32>(region, "tune")?.map_or(0.0, |tune| -tune),
+        key_range: Some(low_key..=high_key),
+        velocity_range: Some(low_velocity..=high_velocity),
+        velocity_hint: None,
+        loop_points,
+    }))
+}
+
+/// Reads the amplitude envelope of a region, or `None` if it doesn't have
+/// one.
+fn envelope(region: &Region) -> Result<Option<AmpEnvelope>> {
+    let attack = parse::<f32>(region, "ampeg_attack")?;
+    let decay = parse::<f32>(region, "ampeg_decay")?;
+    let sustain = parse::<f32>(region, "ampeg_sustain")?;
+    let release = parse::<f32>(region, "ampeg_release")?;
+    if attack.is_none() && decay.is_none() && sustain.is_none() && release.is_none() {
+        return Ok(None);
+    }
+
+    // These are the defaults from the SFZ specification.
+    Ok(Some(AmpEnvelope {
+        attack: attack.unwrap_or(0.0).max(0.0),
+        decay: decay.unwrap_or(0.0).max(0.0),
+        sustain: (sustain.unwrap_or(100.0) / 100.0).clamp(0.0, 1.0),
+        release: release.unwrap_or(0.001).max(0.0),
+    }))
+}
+
+fn parse<T: FromStr>(region: &Region, opcode: &str) -> Result<Option<T>> {
+    region
+        .get(opcode)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| anyhow!("invalid value '{}' for {}", value, opcode))
+        })
+        .transpose()
+}
+
+/// Parses a MIDI note number or a note name like `c#4`, and shifts it by the
+/// `<control>` offsets.
+fn parse_key(value: &str, shift: i32) -> Result<u8> {
+    let note = match value.parse::<i32>() {
+        Ok(note) => note,
+        Err(_) => parse_note_name(value).ok_or(anyhow!("invalid note '{}'", value))? as i32,
+    };
+
+    u8::try_from(note + shift)
+        .ok()
+        .filter(|note| *note <= 127)
+        .ok_or(anyhow!("note {} is out of range", note + shift))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opcodes(region: &Region) -> Vec<(&str, &str)> {
+        region
+            .iter()
+            .map(|(opcode, value)| (opcode.as_str(), value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn regions_inherit_opcodes() {
+        let sfz = SfzFile::parse(
+            "<control> default_path=samples/
+             <global> ampeg_release=0.5
+             <group> lovel=0 hivel=63 loopmode=no_loop
+             <region> sample=soft c4.wav key=60
+             <region> sample=soft d4.wav key=62 hivel=127
+             <curve> v000=0
+             <group> <region>sample=loud.wav",
+        );
+
+        assert_eq!(sfz.regions.len(), 3);
+        assert_eq!(
+            opcodes(&sfz.regions[0]),
+            [
+                ("ampeg_release", "0.5"),
+                ("default_path", "samples/"),
+                ("hivel", "63"),
+                ("key", "60"),
+                ("loop_mode", "no_loop"),
+                ("lovel", "0"),
+                ("sample", "soft c4.wav"),
+            ]
+        );
+        assert_eq!(sfz.regions[1]["hivel"], "127");
+        assert_eq!(
+            opcodes(&sfz.regions[2]),
+            [
+                ("ampeg_release", "0.5"),
+                ("default_path", "samples/"),
+                ("sample", "loud.wav"),
+            ]
+        );
+        assert_eq!(
+            sfz.ignored_headers,
+            BTreeMap::from([("curve".to_string(), 1)])
+        );
+    }
+
+    #[test]
+    fn preprocessing() {
+        let text = "// A comment\n\
+                    #define $ROOT 60\n\
+                    <region> /* sample=b.wav\n */ sample=a/b.wav key=$ROOT // tune=5\n";
+        let text = preprocess(text, Path::new(""), &mut Vec::new(), 0).unwrap();
+        assert_eq!(text, "\n<region> \n sample=a/b.wav key=60 \n");
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse_key("60", 0).unwrap(), 60);
+        assert_eq!(parse_key("c#4", 0).unwrap(), 61);
+        assert_eq!(parse_key("Eb2", 12).unwrap(), 51);
+        assert!(parse_key("127", 1).is_err());
+        assert!(parse_key("x4", 0).is_err());
+    }
+}
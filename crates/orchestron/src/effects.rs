@@ -0,0 +1,86 @@
+//! The ensemble and reverb that give the strings and choirs their width and
+//! space.
+
+use fx::ensemble::Ensemble;
+use fx::freeverb::Freeverb;
+use fx::params::{percentage, ReverbParams};
+use nih_plug::prelude::*;
+
+#[derive(Params)]
+pub struct EffectsParams {
+    #[id = "ensemble"]
+    pub ensemble_enabled: BoolParam,
+    #[id = "ensemble-rate"]
+    pub ensemble_rate: FloatParam,
+    #[id = "ensemble-depth"]
+    pub ensemble_depth: FloatParam,
+    #[id = "ensemble-mix"]
+    pub ensemble_mix: FloatParam,
+
+    #[nested]
+    pub reverb: ReverbParams,
+}
+
+impl Default for EffectsParams {
+    fn default() -> Self {
+        Self {
+            ensemble_enabled: BoolParam::new("Ensemble", false),
+            ensemble_rate: FloatParam::new(
+                "Ensemble Rate",
+                0.6,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 5.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" Hz"),
+            ensemble_depth: percentage("Ensemble Depth", 0.5),
+            ensemble_mix: percentage("Ensemble Mix", 0.5),
+
+            reverb: ReverbParams::default(),
+        }
+    }
+}
+
+/// The state of the effects at a sample rate.
+pub struct Effects {
+    ensemble: Ensemble,
+    reverb: Freeverb,
+}
+
+impl Effects {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            ensemble: Ensemble::new(sample_rate),
+            reverb: Freeverb::new(sample_rate as usize),
+        }
+    }
+
+    /// Clears the delay lines and the reverb tail.
+    pub fn reset(&mut self) {
+        self.ensemble.reset();
+        self.reverb.reset();
+    }
+
+    /// Updates the reverb from the parameters. This is done once per block.
+    pub fn update(&mut self, params: &EffectsParams) {
+        params.reverb.update(&mut self.reverb);
+    }
+
+    /// Runs a mono sample through the enabled effects, which makes it stereo.
+    pub fn process(&mut self, params: &EffectsParams, input: f32) -> (f32, f32) {
+        let mut output = (input, input);
+
+        if params.ensemble_enabled.value() {
+            output = self.ensemble.process(
+                output,
+                params.ensemble_rate.value(),
+                params.ensemble_depth.value(),
+                params.ensemble_mix.value(),
+            );
+        }
+
+        params.reverb.process(&mut self.reverb, output)
+    }
+}
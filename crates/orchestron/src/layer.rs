@@ -0,0 +1,107 @@
+//! The two sounds Orchestron plays at once, either layered on top of each
+//! other or split across the keyboard.
+
+use engine::{InstrumentSlot, Voice, Voices};
+use instrument::Instrument;
+use nih_plug::prelude::Enum;
+
+/// The most voices a layer plays at once.
+const MAX_VOICES: usize = 64;
+
+/// How layer B is combined with layer A.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum LayerMode {
+    /// Only layer A plays.
+    Off,
+    /// Layer B plays along with layer A in its key range.
+    Layer,
+    /// Layer B plays in its key range and layer A everywhere else.
+    Split,
+}
+
+impl LayerMode {
+    /// Returns whether layers A and B play a note, given whether the note is
+    /// in layer B's key range.
+    pub fn layers(self, in_range: bool) -> (bool, bool) {
+        match self {
+            LayerMode::Off => (true, false),
+            LayerMode::Layer => (true, in_range),
+            LayerMode::Split => (!in_range, in_range),
+        }
+    }
+}
+
+/// Identifies a layer in background tasks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LayerId {
+    A,
+    B,
+}
+
+/// An instrument and the voices that play it.
+pub struct Layer {
+    pub slot: InstrumentSlot,
+    voices: Voices,
+}
+
+impl Layer {
+    pub fn new() -> Self {
+        Self {
+            slot: InstrumentSlot::new(),
+            voices: Voices::new(MAX_VOICES),
+        }
+    }
+
+    /// See [`InstrumentSlot::replace_loaded`].
+    pub fn replace_loaded(&mut self) {
+        self.slot.replace_loaded(&mut [&mut self.voices]);
+    }
+
+    /// See [`InstrumentSlot::swap_loaded`].
+    pub fn swap_loaded(&mut self, fade_out_samples: u32) -> Option<Box<Instrument>> {
+        self.slot
+            .swap_loaded(&mut [&mut self.voices], fade_out_samples)
+    }
+
+    /// See [`InstrumentSlot::take_retired`].
+    pub fn take_retired(&mut self) -> Option<Box<Instrument>> {
+        self.slot.take_retired(&[&self.voices])
+    }
+
+    /// Starts a voice, fading out the oldest one over a number of samples if
+    /// the layer plays too many.
+    pub fn start_voice(&mut self, voice: Voice, fade_out_samples: u32) {
+        self.voices.start(voice, fade_out_samples);
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        self.voices.note_off(note);
+    }
+
+    /// Sums the next sample of all voices and removes the ones that ended.
+    pub fn next_sample(&mut self) -> f32 {
+        let output = self.voices.next_sample();
+        self.voices.remove_finished();
+
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.voices.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_pick_the_layers() {
+        assert_eq!(LayerMode::Off.layers(true), (true, false));
+        assert_eq!(LayerMode::Off.layers(false), (true, false));
+        assert_eq!(LayerMode::Layer.layers(true), (true, true));
+        assert_eq!(LayerMode::Layer.layers(false), (true, false));
+        assert_eq!(LayerMode::Split.layers(true), (false, true));
+        assert_eq!(LayerMode::Split.layers(false), (true, false));
+    }
+}
@@ -4,24 +4,38 @@ use std::sync::{
     Arc,
 };
 
-use common::resampler::{calc_hertz, resample};
-use engine::{Adsr, Voice};
-use instrument::Instrument;
+use effects::{Effects, EffectsParams};
+use engine::library::LibraryParams;
+use engine::{Adsr, Task, Voice};
+use instrument::metadata::AmpEnvelope;
+use instrument::{legacy, library, Instrument};
+use layer::{Layer, LayerId, LayerMode};
 use nih_plug::prelude::*;
 use presets::Presets;
+use tape::Transport;
 
-pub mod instrument;
+mod effects;
+mod layer;
 mod presets;
+mod tape;
 
 const DEFAULT_ATTACK_S: f32 = 0.01;
 const DEFAULT_DECAY_S: f32 = 0.1;
 const DEFAULT_SUSTAIN_LEVEL: f32 = 1.0;
 const DEFAULT_RELEASE_S: f32 = 0.2;
+/// How long the voices of the previous instrument take to fade out after
+/// switching instruments.
+const FADE_OUT_S: f32 = 0.02;
+/// The reference pitch of A4 in Hz that instruments are recorded at.
+const DEFAULT_MASTER_TUNE_HZ: f32 = 440.0;
 
 struct Orchestron {
     params: Arc<OrchestronParams>,
-    voices: Vec<Voice>,
-    instrument: Instrument,
+    layer_a: Layer,
+    layer_b: Layer,
+    /// The wow and flutter of tape mode.
+    transport: Transport,
+    effects: Effects,
     sample_rate: f32,
     adsr: Adsr,
 }
@@ -38,9 +52,53 @@ struct OrchestronParams {
     pub sustain: FloatParam,
     #[id = "release"]
     pub release: FloatParam,
+    /// The pitch of A4 in Hz.
+    #[id = "tune"]
+    pub master_tune: FloatParam,
+    /// Shifts every note by semitones.
+    #[id = "transpose"]
+    pub transpose: IntParam,
+    /// Shifts every note by octaves.
+    #[id = "octave"]
+    pub octave: IntParam,
+    /// Plays notes like a tape replay keyboard, where they stop once the tape
+    /// runs out.
+    #[id = "tape"]
+    pub tape_mode: BoolParam,
+    #[id = "wow-flutter"]
+    pub wow_flutter: FloatParam,
     #[id = "preset"]
     pub preset: EnumParam<Presets>,
+    #[nested]
+    pub library: LibraryParams,
     pub preset_change: Arc<AtomicBool>,
+    #[id = "layer-mode"]
+    pub layer_mode: EnumParam<LayerMode>,
+    #[id = "preset-b"]
+    pub preset_b: EnumParam<Presets>,
+    #[id = "level-b"]
+    pub level_b: FloatParam,
+    /// Shifts the notes of layer B by semitones, on top of the transpose and
+    /// octave of both layers.
+    #[id = "transpose-b"]
+    pub transpose_b: IntParam,
+    /// The lowest key layer B plays.
+    #[id = "low-key-b"]
+    pub low_key_b: IntParam,
+    /// The highest key layer B plays.
+    #[id = "high-key-b"]
+    pub high_key_b: IntParam,
+    pub preset_b_change: Arc<AtomicBool>,
+    #[nested(group = "Effects")]
+    pub effects: EffectsParams,
+}
+
+/// Loads the instrument of a layer. With `select` the library path of layer A
+/// is updated from the selection first, which isn't done when the state is
+/// restored.
+pub struct Load {
+    layer: LayerId,
+    select: bool,
 }
 
 impl Default for Orchestron {
@@ -49,8 +107,10 @@ impl Default for Orchestron {
 
         Self {
             params: Arc::new(OrchestronParams::default()),
-            voices: Vec::new(),
-            instrument: Instrument::default(),
+            layer_a: Layer::new(),
+            layer_b: Layer::new(),
+            transport: Transport::new(sample_rate),
+            effects: Effects::new(sample_rate),
             sample_rate,
             adsr: Adsr::new(sample_rate),
         }
@@ -60,6 +120,7 @@ impl Default for Orchestron {
 impl Default for OrchestronParams {
     fn default() -> Self {
         let preset_change = Arc::new(AtomicBool::new(false));
+        let preset_b_change = Arc::new(AtomicBool::new(false));
 
         Self {
             gain: FloatParam::new(
@@ -114,13 +175,74 @@ impl Default for OrchestronParams {
                 },
             )
             .with_unit(" s"),
+            master_tune: FloatParam::new(
+                "Master Tune",
+                DEFAULT_MASTER_TUNE_HZ,
+                FloatRange::Linear {
+                    min: 415.0,
+                    max: 466.0,
+                },
+            )
+            .with_step_size(0.1)
+            .with_unit(" Hz"),
+            transpose: IntParam::new("Transpose", 0, IntRange::Linear { min: -12, max: 12 })
+                .with_unit(" st"),
+            octave: IntParam::new("Octave", 0, IntRange::Linear { min: -3, max: 3 }),
+            tape_mode: BoolParam::new("Tape Mode", false),
+            wow_flutter: FloatParam::new(
+                "Wow & Flutter",
+                0.5,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
             preset: EnumParam::new("Preset", Presets::default()).with_callback({
                 let preset_change = preset_change.clone();
                 Arc::new(move |_| {
                     preset_change.store(true, Ordering::Relaxed);
                 })
             }),
+            library: LibraryParams::new(Orchestron::NAME, preset_change.clone()),
             preset_change,
+            layer_mode: EnumParam::new("Layer Mode", LayerMode::Off),
+            preset_b: EnumParam::new("Layer B Preset", Presets::default()).with_callback({
+                let preset_b_change = preset_b_change.clone();
+                Arc::new(move |_| {
+                    preset_b_change.store(true, Ordering::Relaxed);
+                })
+            }),
+            level_b: FloatParam::new(
+                "Layer B Level",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            transpose_b: IntParam::new(
+                "Layer B Transpose",
+                0,
+                IntRange::Linear { min: -24, max: 24 },
+            )
+            .with_unit(" st"),
+            low_key_b: IntParam::new("Layer B Low Key", 0, IntRange::Linear { min: 0, max: 127 })
+                .with_value_to_string(formatters::v2s_i32_note_formatter())
+                .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            high_key_b: IntParam::new(
+                "Layer B High Key",
+                127,
+                IntRange::Linear { min: 0, max: 127 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            preset_b_change,
+            effects: EffectsParams::default(),
         }
     }
 }
@@ -150,28 +272,82 @@ impl Plugin for Orchestron {
 
     type SysExMessage = ();
 
-    type BackgroundTask = ();
+    type BackgroundTask = Task<Load>;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let loaded_a = self.layer_a.slot.loaded.clone();
+        let loaded_b = self.layer_b.slot.loaded.clone();
+
+        Box::new(move |task| {
+            let loaded = match &task {
+                Task::Load(Load {
+                    layer: LayerId::B, ..
+                }) => &loaded_b,
+                _ => &loaded_a,
+            };
+            task.run(loaded, |Load { layer, select }| match layer {
+                LayerId::A => {
+                    if select {
+                        params.library.select();
+                    }
+                    params.load_instrument()
+                }
+                LayerId::B => params.load_preset(params.preset_b.value()),
+            })
+        })
+    }
+
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
         self.sample_rate = buffer_config.sample_rate;
-        if self.instrument.sample.is_empty() {
-            self.load_preset(self.params.preset.value());
+        // Restoring the state also triggers the parameter callbacks, but then
+        // the persisted library path is loaded rather than the selection.
+        // This isn't the audio thread, so the instruments are loaded right
+        // away and playback starts with them.
+        let changed = self.params.preset_change.swap(false, Ordering::Relaxed);
+        if changed || self.layer_a.slot.instrument.zones.is_empty() {
+            context.execute(Task::Load(Load {
+                layer: LayerId::A,
+                select: false,
+            }));
+            self.layer_a.replace_loaded();
         }
+        let changed = self.params.preset_b_change.swap(false, Ordering::Relaxed);
+        if changed || self.layer_b.slot.instrument.zones.is_empty() {
+            context.execute(Task::Load(Load {
+                layer: LayerId::B,
+                select: false,
+            }));
+            self.layer_b.replace_loaded();
+        }
+
+        // The envelope times are in seconds, so they're kept.
+        self.adsr.set_sample_rate(self.sample_rate);
+        self.transport = Transport::new(self.sample_rate);
+        self.effects = Effects::new(self.sample_rate);
 
         true
     }
 
     fn reset(&mut self) {
-        self.voices.clear();
+        self.layer_a.reset();
+        self.layer_b.reset();
+        self.transport.reset();
+        self.effects.reset();
+        self.params.gain.smoothed.reset(self.params.gain.value());
+        self.params
+            .level_b
+            .smoothed
+            .reset(self.params.level_b.value());
     }
 
     fn process(
@@ -180,14 +356,34 @@ impl Plugin for Orchestron {
         _aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        let mut next_event = context.next_event();
+        // Swap in instruments that were loaded in the background.
+        let fade_out_samples = (FADE_OUT_S * self.sample_rate) as u32;
+        for layer in [&mut self.layer_a, &mut self.layer_b] {
+            if let Some(instrument) = layer.swap_loaded(fade_out_samples) {
+                context.execute_background(Task::Drop(instrument));
+            }
+        }
 
+        let mut next_event = context.next_event();
+        let tape_mode = self.params.tape_mode.value();
+        let layer_mode = self.params.layer_mode.value();
+
+        // An instrument on layer A that was imported with an amp envelope is
+        // played with it instead of the envelope knobs.
+        let envelope =
+            AmpEnvelope::read(&self.layer_a.slot.instrument.metadata).unwrap_or(AmpEnvelope {
+                attack: self.params.attack.value(),
+                decay: self.params.decay.value(),
+                sustain: self.params.sustain.value(),
+                release: self.params.release.value(),
+            });
         self.adsr.set_parameters(
-            self.params.attack.value(),
-            self.params.decay.value(),
-            self.params.sustain.value(),
-            self.params.release.value(),
+            envelope.attack,
+            envelope.decay,
+            envelope.sustain,
+            envelope.release,
         );
+        self.effects.update(&self.params.effects);
 
         for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
             while let Some(event) = next_event {
@@ -197,26 +393,24 @@ impl Plugin for Orchestron {
 
                 match event {
                     NoteEvent::NoteOn { note, velocity, .. } => {
-                        let playback_rate = calc_hertz(self.sample_rate, 53 - (note as i32));
-
-                        let resampled = resample(&self.instrument.sample, 44100.0, playback_rate);
-
-                        let new_voice = Voice::new(
-                            Arc::new(resampled),
-                            note,
-                            velocity,
-                            self.adsr.clone(),
-                            true,
-                        );
-
-                        self.voices.push(new_voice);
+                        let low_key = self.params.low_key_b.value();
+                        let high_key = self.params.high_key_b.value();
+                        let in_range = (low_key..=high_key).contains(&(note as i32));
+                        let (play_a, play_b) = layer_mode.layers(in_range);
+
+                        let shift = self.params.transpose.value() + 12 * self.params.octave.value();
+                        if play_a {
+                            self.start_voice(LayerId::A, note, shift, velocity);
+                        }
+                        if play_b {
+                            let shift = shift + self.params.transpose_b.value();
+                            self.start_voice(LayerId::B, note, shift, velocity);
+                        }
                     }
 
                     NoteEvent::NoteOff { note, .. } => {
-                        self.voices
-                            .iter_mut()
-                            .filter(|v| v.matches_note(note))
-                            .for_each(|v| v.note_off());
+                        self.layer_a.note_off(note);
+                        self.layer_b.note_off(note);
                     }
 
                     _ => (),
@@ -225,39 +419,120 @@ impl Plugin for Orchestron {
                 next_event = context.next_event();
             }
 
-            let mut output_sample = 0.0;
-            for voice in &mut self.voices {
-                output_sample += voice.next_sample();
+            let level_b = self.params.level_b.smoothed.next();
+            let mut output_sample =
+                self.layer_a.next_sample() + self.layer_b.next_sample() * level_b;
+
+            if tape_mode {
+                output_sample = self
+                    .transport
+                    .process(output_sample, self.params.wow_flutter.value());
             }
 
+            let (left, right) = self.effects.process(&self.params.effects, output_sample);
             let gain = self.params.gain.smoothed.next();
-            output_sample *= gain;
+            let (left, right) = (left * gain, right * gain);
 
-            for sample in channel_samples {
-                *sample = output_sample;
+            // Even channels are left and odd ones right.
+            for (channel, sample) in channel_samples.into_iter().enumerate() {
+                *sample = if channel % 2 == 0 { left } else { right };
             }
+        }
 
-            self.voices.retain(|v| v.is_active());
+        // Once the voices of a previous instrument are gone, it can go too.
+        for layer in [&mut self.layer_a, &mut self.layer_b] {
+            if let Some(instrument) = layer.take_retired() {
+                context.execute_background(Task::Drop(instrument));
+            }
         }
 
-        if self.params.preset_change.swap(false, Ordering::Relaxed)
-            && self.instrument.name != self.params.preset.value().to_string()
-        {
-            self.load_preset(self.params.preset.value());
+        if self.params.preset_change.swap(false, Ordering::Relaxed) {
+            context.execute_background(Task::Load(Load {
+                layer: LayerId::A,
+                select: true,
+            }));
+        }
+        if self.params.preset_b_change.swap(false, Ordering::Relaxed) {
+            context.execute_background(Task::Load(Load {
+                layer: LayerId::B,
+                select: false,
+            }));
         }
         ProcessStatus::Normal
     }
 }
 
 impl Orchestron {
-    pub fn load_preset(&mut self, preset: Presets) {
-        self.voices.clear();
-
-        let instrument_data = preset.content().to_vec();
-        // Spawning a thread to decode the instrument data.
-        self.instrument = std::thread::spawn(move || Instrument::decode(instrument_data))
-            .join()
-            .expect("Failed to load preset on a different thread");
+    /// Starts a voice on a layer for a note, shifted by semitones.
+    fn start_voice(&mut self, layer: LayerId, note: u8, shift: i32, velocity: f32) {
+        let tape_mode = self.params.tape_mode.value();
+        let layer = match layer {
+            LayerId::A => &mut self.layer_a,
+            LayerId::B => &mut self.layer_b,
+        };
+
+        // Zones are picked by the note that sounds, so transposing doesn't
+        // stretch a sample further than the instrument meant it to be.
+        let pitch = (note as i32 + shift).clamp(0, 127) as u8;
+        let instrument = &layer.slot.instrument;
+        let Some(zone) = instrument.find_zone(pitch, velocity) else {
+            return;
+        };
+
+        // In tape mode, every key has its own tape, which needs to get up to
+        // speed and eventually runs out.
+        let (speed, gain) = if tape_mode {
+            tape::key_variation(pitch)
+        } else {
+            (1.0, 1.0)
+        };
+
+        // The master tune and the speed of the tape detune the note on top of
+        // the zone's own tuning.
+        let tuning = (self.params.master_tune.value() / DEFAULT_MASTER_TUNE_HZ) as f64 * speed;
+        let mut voice = Voice::from_zone(
+            instrument,
+            zone,
+            note,
+            pitch as f64 + 12.0 * tuning.log2(),
+            velocity * gain,
+            self.adsr.clone(),
+            self.sample_rate,
+        );
+        if tape_mode {
+            voice = voice
+                .with_speed_up(
+                    tape::TAPE_START_SPEED,
+                    (tape::TAPE_START_S * self.sample_rate) as u32,
+                )
+                .with_length(
+                    (tape::TAPE_LENGTH_S * self.sample_rate) as u32,
+                    (tape::TAPE_STOP_S * self.sample_rate) as u32,
+                );
+        }
+
+        layer.start_voice(voice, (FADE_OUT_S * self.sample_rate) as u32);
+    }
+}
+
+impl OrchestronParams {
+    /// Loads the user instrument from the library, or the preset if there is
+    /// none or it can't be loaded.
+    fn load_instrument(&self) -> Option<Instrument> {
+        self.library
+            .load(library::load)
+            .or_else(|| self.load_preset(self.preset.value()))
+    }
+
+    /// Loads one of the built-in presets.
+    fn load_preset(&self, preset: Presets) -> Option<Instrument> {
+        match legacy::decode_orchestron(preset.content()) {
+            Ok(instrument) => Some(instrument),
+            Err(e) => {
+                nih_error!("Failed to decode the {} preset: {}", preset, e);
+                None
+            }
+        }
     }
 }
 
@@ -23,7 +23,7 @@ pub const SAXOPHONE: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "orchestron
 pub const VIOLINS: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "orchestron/violins"));
 
 impl Presets {
-    pub fn content(&self) -> &[u8] {
+    pub fn content(&self) -> &'static [u8] {
         match self {
             Presets::Cello => CELLO,
             Presets::Choir => CHOIR,
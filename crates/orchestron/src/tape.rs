@@ -0,0 +1,137 @@
+//! Tape mode, which plays the instrument like a tape replay keyboard: every
+//! key has its own strip of tape that runs out, and the transport adds wow and
+//! flutter.
+
+use std::f32::consts::PI;
+
+use fx::delay_line::StereoDelay;
+use fx::{
+    FLUTTER_MAX_FREQUENCY_RATIO,
+    FLUTTER_MAX_LFO_FREQUENCY,
+    MAX_DELAY_TIME_SECONDS,
+    WOW_MAX_FREQUENCY_RATIO,
+    WOW_MAX_LFO_FREQUENCY,
+};
+
+/// How long a note plays before the tape runs out, in seconds.
+pub const TAPE_LENGTH_S: f32 = 8.0;
+/// How long the sound takes to stop when the tape runs out, in seconds.
+pub const TAPE_STOP_S: f32 = 0.03;
+/// How long the tape takes to get up to speed after a key is pressed, in
+/// seconds.
+pub const TAPE_START_S: f32 = 0.05;
+/// The speed the tape starts at, relative to its full speed.
+pub const TAPE_START_SPEED: f64 = 0.97;
+
+/// How far the pitch of a key's tape may be off, in cents.
+const KEY_DETUNE_CENTS: f64 = 4.0;
+/// How far the level of a key's tape may be off, in dB.
+const KEY_LEVEL_DB: f32 = 1.0;
+
+/// Returns how the tape of a key differs from the others, as a factor on the
+/// playback rate and a gain. Every key always gets the same values, like the
+/// tapes in a real instrument.
+pub fn key_variation(note: u8) -> (f64, f32) {
+    // Spread the bits of the note with MurmurHash3's finalizer, so
+    // neighboring keys aren't similar.
+    let mut hash = note as u32 + 1;
+    hash = (hash ^ (hash >> 16)).wrapping_mul(0x85eb_ca6b);
+    hash = (hash ^ (hash >> 13)).wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    let unit = |bits: u32| (bits & 0xffff) as f32 / 0xffff as f32 * 2.0 - 1.0;
+
+    let cents = unit(hash) as f64 * KEY_DETUNE_CENTS;
+    let db = unit(hash >> 16) * KEY_LEVEL_DB;
+
+    (2.0_f64.powf(cents / 1200.0), 10.0_f32.powf(db / 20.0))
+}
+
+/// The wow and flutter of the tape transport.
+pub struct Transport {
+    wow: StereoDelay,
+    flutter: StereoDelay,
+}
+
+impl Transport {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            wow: StereoDelay::new(MAX_DELAY_TIME_SECONDS, sample_rate as usize),
+            flutter: StereoDelay::new(MAX_DELAY_TIME_SECONDS, sample_rate as usize),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.wow.reset();
+        self.flutter.reset();
+    }
+
+    /// Modulates the pitch of a sample. At an `amount` of 1, the maximum
+    /// frequency ratios of the `fx` crate are reached, read as percentages.
+    pub fn process(&mut self, input: f32, amount: f32) -> f32 {
+        let (output, _) = self.wow.process_with_vibrato(
+            (input, input),
+            WOW_MAX_LFO_FREQUENCY,
+            vibrato_width(amount * WOW_MAX_FREQUENCY_RATIO, WOW_MAX_LFO_FREQUENCY),
+            0.0,
+        );
+        let (output, _) = self.flutter.process_with_vibrato(
+            (output, output),
+            FLUTTER_MAX_LFO_FREQUENCY,
+            vibrato_width(
+                amount * FLUTTER_MAX_FREQUENCY_RATIO,
+                FLUTTER_MAX_LFO_FREQUENCY,
+            ),
+            0.0,
+        );
+
+        output
+    }
+}
+
+/// Returns the delay sweep in seconds that makes a sine LFO bend the pitch by
+/// up to `percent`.
+fn vibrato_width(percent: f32, lfo_frequency: f32) -> f32 {
+    // The delay is `width / 2 * (1 + sin(2 pi f t))`, so the pitch changes by
+    // its derivative, which peaks at `pi * f * width`.
+    percent / 100.0 / (PI * lfo_frequency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_variation_is_small_and_fixed_per_key() {
+        let max_rate = 2.0_f64.powf(KEY_DETUNE_CENTS / 1200.0);
+        let max_gain = 10.0_f32.powf(KEY_LEVEL_DB / 20.0);
+
+        for note in 0..128 {
+            let (rate, gain) = key_variation(note);
+            assert_eq!(key_variation(note), (rate, gain));
+            assert!(
+                (1.0 / max_rate..=max_rate).contains(&rate),
+                "{note}: {rate}"
+            );
+            assert!(
+                (1.0 / max_gain..=max_gain).contains(&gain),
+                "{note}: {gain}"
+            );
+        }
+        assert_ne!(key_variation(60), key_variation(61));
+    }
+
+    #[test]
+    fn vibrato_width_bends_the_pitch_by_the_percentage() {
+        let lfo_frequency = 5.0;
+        let width = vibrato_width(2.0, lfo_frequency);
+
+        // The playback rate is 1 minus how fast the delay changes.
+        let delay = |t: f32| width / 2.0 * (1.0 + (2.0 * PI * lfo_frequency * t).sin());
+        let dt = 1e-4;
+        let max_bend = (0..2000)
+            .map(|i| i as f32 * dt)
+            .map(|t| (delay(t + dt) - delay(t)) / dt)
+            .fold(0.0_f32, |max, bend| max.max(bend.abs()));
+        assert!((max_bend - 0.02).abs() < 1e-4, "{max_bend}");
+    }
+}
@@ -0,0 +1,137 @@
+//! Single-finger chords: the keys held in the lower split pick a chord for the
+//! accompaniment, like on home keyboards.
+
+/// The intervals of the chords in semitones above the root, by the number of
+/// keys that are held: one key plays a major chord, two a minor chord, three a
+/// seventh chord and four or more a minor seventh chord.
+const CHORDS: [&[i32]; 4] = [&[0, 4, 7], &[0, 3, 7], &[0, 4, 7, 10], &[0, 3, 7, 10]];
+/// How far below the chord the bass plays, in semitones.
+const BASS_OFFSET: i32 = -12;
+
+/// The notes of a chord.
+#[derive(Clone, Copy, Default)]
+pub struct Chord {
+    notes: [u8; 4],
+    len: usize,
+}
+
+impl Chord {
+    pub fn notes(&self) -> &[u8] {
+        &self.notes[..self.len]
+    }
+}
+
+/// The keys that are held in the lower split.
+pub struct ChordKeys {
+    /// The held keys, in the order they were pressed. Room for every key is
+    /// reserved up front, so pressing keys doesn't allocate.
+    keys: Vec<u8>,
+    /// The velocity of the last key that was pressed, which the chord is
+    /// played at when it changes.
+    velocity: f32,
+}
+
+impl ChordKeys {
+    pub fn new() -> Self {
+        Self {
+            keys: Vec::with_capacity(128),
+            velocity: 1.0,
+        }
+    }
+
+    pub fn press(&mut self, note: u8, velocity: f32) {
+        self.velocity = velocity;
+        if !self.keys.contains(&note) {
+            self.keys.push(note);
+        }
+    }
+
+    pub fn release(&mut self, note: u8) {
+        self.keys.retain(|&key| key != note);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+
+    /// Returns the notes of the chord, which are none if no keys are held.
+    /// The lowest key is the root.
+    pub fn chord(&self) -> Chord {
+        let mut chord = Chord::default();
+        let Some(&root) = self.keys.iter().min() else {
+            return chord;
+        };
+
+        let intervals = CHORDS[self.keys.len().min(CHORDS.len()) - 1];
+        for interval in intervals {
+            let note = root as i32 + interval;
+            if note < 128 {
+                chord.notes[chord.len] = note as u8;
+                chord.len += 1;
+            }
+        }
+
+        chord
+    }
+
+    /// Returns the bass note for the chord, if keys are held.
+    pub fn bass(&self) -> Option<u8> {
+        let root = *self.keys.iter().min()? as i32;
+        // The bass moves up an octave if it would drop off the keyboard.
+        let bass = root + BASS_OFFSET;
+        let bass = if bass < 0 { root } else { bass };
+        Some(bass as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(notes: &[u8]) -> ChordKeys {
+        let mut keys = ChordKeys::new();
+        for &note in notes {
+            keys.press(note, 0.5);
+        }
+        keys
+    }
+
+    #[test]
+    fn held_keys_pick_the_chord() {
+        assert!(keys(&[]).chord().notes().is_empty());
+        assert_eq!(keys(&[48]).chord().notes(), [48, 52, 55]);
+        assert_eq!(keys(&[50, 48]).chord().notes(), [48, 51, 55]);
+        assert_eq!(keys(&[48, 50, 52]).chord().notes(), [48, 52, 55, 58]);
+        assert_eq!(
+            keys(&[48, 50, 52, 53, 55]).chord().notes(),
+            [48, 51, 55, 58]
+        );
+
+        let mut keys = keys(&[48, 50]);
+        keys.release(50);
+        assert_eq!(keys.chord().notes(), [48, 52, 55]);
+        assert_eq!(keys.velocity(), 0.5);
+    }
+
+    #[test]
+    fn drops_notes_above_the_keyboard() {
+        assert_eq!(keys(&[122]).chord().notes(), [122, 126]);
+        assert_eq!(keys(&[127]).chord().notes(), [127]);
+    }
+
+    #[test]
+    fn bass_stays_on_the_keyboard() {
+        assert_eq!(keys(&[]).bass(), None);
+        assert_eq!(keys(&[50, 48]).bass(), Some(36));
+        assert_eq!(keys(&[12]).bass(), Some(0));
+        assert_eq!(keys(&[5]).bass(), Some(5));
+    }
+}
@@ -1,30 +1,159 @@
-use std::sync::Arc;
+#![allow(non_snake_case, non_upper_case_globals)]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
+use chord::ChordKeys;
+use engine::library::LibraryParams;
+use engine::{Adsr, InstrumentSlot, Task, Voice, Voices};
+use instrument::metadata::AmpEnvelope;
+use instrument::{library, Instrument};
+use lofi::{LoFi, LoFiParams};
 use nih_plug::prelude::*;
+use presets::Presets;
+use rhythm::{Pattern, Rhythms, Sequencer};
 
-// pub mod instrument;
-mod resources;
+mod chord;
+mod lofi;
+mod presets;
+mod rhythm;
+
+const DEFAULT_ATTACK_S: f32 = 0.0;
+const DEFAULT_DECAY_S: f32 = 0.1;
+const DEFAULT_SUSTAIN_LEVEL: f32 = 1.0;
+const DEFAULT_RELEASE_S: f32 = 0.1;
+/// How long the voices of the previous instrument take to fade out after
+/// switching instruments.
+const FADE_OUT_S: f32 = 0.02;
+/// How many notes play at once. Like on a toy keyboard, the oldest note is
+/// cut off when there are more.
+const MAX_VOICES: usize = 8;
+/// How many drum hits ring at once.
+const MAX_RHYTHM_VOICES: usize = 16;
+/// How many bass and chord notes of the accompaniment play at once.
+const MAX_ACCOMPANIMENT_VOICES: usize = 10;
+/// How many steps of a pattern a bass or chord note lasts.
+const ACCOMPANIMENT_STEPS: f32 = 1.5;
+/// The highest key of the lower split by default, F#3.
+const DEFAULT_SPLIT_KEY: i32 = 54;
 
 struct Toybox {
     params: Arc<ToyboxParams>,
+    voices: Voices,
+    slot: InstrumentSlot,
+    /// The bass and chord notes of the accompaniment, which play the
+    /// instrument as well.
+    accompaniment: Voices,
+    /// The keys that are held in the lower split while auto chord is on.
+    chord_keys: ChordKeys,
+    /// The drum kit of the rhythm box and the hits that are ringing.
+    drums: Box<Instrument>,
+    rhythm_voices: Voices,
+    /// The rhythm patterns, in the order of [`Rhythms`]. Patterns that can't
+    /// be parsed are left out.
+    patterns: Vec<Option<Pattern>>,
+    sequencer: Sequencer,
+    lofi: LoFi,
+    sample_rate: f32,
+    adsr: Adsr,
+}
+
+/// Loads the instrument. With `select` the library path is updated from the
+/// selection first, which isn't done when the state is restored.
+pub struct Load {
+    select: bool,
 }
 
 #[derive(Params)]
 struct ToyboxParams {
     #[id = "gain"]
     pub gain: FloatParam,
+    #[id = "attack"]
+    pub attack: FloatParam,
+    #[id = "decay"]
+    pub decay: FloatParam,
+    #[id = "sustain"]
+    pub sustain: FloatParam,
+    #[id = "release"]
+    pub release: FloatParam,
+    /// Shifts every note by semitones.
+    #[id = "transpose"]
+    pub transpose: IntParam,
+    #[id = "preset"]
+    pub preset: EnumParam<Presets>,
+    #[nested]
+    pub library: LibraryParams,
+    // This flag is used to signal the audio thread that the preset has changed.
+    pub preset_change: Arc<AtomicBool>,
+    /// Plays the rhythm pattern while the host's transport is running.
+    #[id = "rhythm"]
+    pub rhythm: BoolParam,
+    #[id = "pattern"]
+    pub pattern: EnumParam<Rhythms>,
+    #[id = "rhythm-level"]
+    pub rhythm_level: FloatParam,
+    /// Turns the keys in the lower split into chords for the accompaniment.
+    #[id = "auto-chord"]
+    pub auto_chord: BoolParam,
+    /// The highest key of the lower split.
+    #[id = "split-key"]
+    pub split_key: IntParam,
+    #[id = "accompaniment-level"]
+    pub accompaniment_level: FloatParam,
+    #[nested(group = "Lo-Fi")]
+    pub lofi: LoFiParams,
 }
 
 impl Default for Toybox {
     fn default() -> Self {
+        let sample_rate = 44100.0;
+
         Self {
             params: Arc::new(ToyboxParams::default()),
+            voices: Voices::new(MAX_VOICES),
+            slot: InstrumentSlot::new(),
+            accompaniment: Voices::new(MAX_ACCOMPANIMENT_VOICES),
+            chord_keys: ChordKeys::new(),
+            drums: Box::default(),
+            rhythm_voices: Voices::new(MAX_RHYTHM_VOICES),
+            patterns: (0..Rhythms::variants().len())
+                .map(Rhythms::from_index)
+                .map(|rhythm| match Pattern::parse(rhythm.content()) {
+                    Ok(pattern) => Some(pattern),
+                    Err(e) => {
+                        nih_error!("Failed to parse the {} pattern: {}", rhythm, e);
+                        None
+                    }
+                })
+                .collect(),
+            sequencer: Sequencer::default(),
+            lofi: LoFi::default(),
+            sample_rate,
+            adsr: Adsr::new(sample_rate),
         }
     }
 }
 
 impl Default for ToyboxParams {
     fn default() -> Self {
+        let preset_change = Arc::new(AtomicBool::new(false));
+        let level = |name: &str| {
+            FloatParam::new(
+                name,
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db())
+        };
+
         Self {
             gain: FloatParam::new(
                 "Gain",
@@ -39,15 +168,75 @@ impl Default for ToyboxParams {
             .with_unit(" dB")
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            attack: FloatParam::new(
+                "Attack",
+                DEFAULT_ATTACK_S,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 2.0,
+                    factor: 0.25,
+                },
+            )
+            .with_unit(" s"),
+            decay: FloatParam::new(
+                "Decay",
+                DEFAULT_DECAY_S,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 2.0,
+                    factor: 0.25,
+                },
+            )
+            .with_unit(" s"),
+            sustain: FloatParam::new(
+                "Sustain",
+                DEFAULT_SUSTAIN_LEVEL,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit(" level")
+            .with_value_to_string(formatters::v2s_f32_percentage(2)),
+            release: FloatParam::new(
+                "Release",
+                DEFAULT_RELEASE_S,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 5.0,
+                    factor: 0.25,
+                },
+            )
+            .with_unit(" s"),
+            transpose: IntParam::new("Transpose", 0, IntRange::Linear { min: -12, max: 12 })
+                .with_unit(" st"),
+            preset: EnumParam::new("Preset", Presets::default()).with_callback({
+                let preset_change = preset_change.clone();
+                Arc::new(move |_| {
+                    preset_change.store(true, Ordering::Relaxed);
+                })
+            }),
+            library: LibraryParams::new(Toybox::NAME, preset_change.clone()),
+            preset_change,
+            rhythm: BoolParam::new("Rhythm", false),
+            pattern: EnumParam::new("Pattern", Rhythms::default()),
+            rhythm_level: level("Rhythm Level"),
+            auto_chord: BoolParam::new("Auto Chord", false),
+            split_key: IntParam::new(
+                "Split Key",
+                DEFAULT_SPLIT_KEY,
+                IntRange::Linear { min: 0, max: 127 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            accompaniment_level: level("Accompaniment Level"),
+            lofi: LoFiParams::default(),
         }
     }
 }
 
 impl Plugin for Toybox {
     const NAME: &'static str = "Toybox";
-    const VENDOR: &'static str = "ZMANN";
+    const VENDOR: &'static str = env!("PKG_VENDOR");
     const URL: &'static str = env!("CARGO_PKG_HOMEPAGE");
-    const EMAIL: &'static str = "your@email.com";
+    const EMAIL: &'static str = env!("PKG_EMAIL");
 
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
@@ -68,60 +257,339 @@ impl Plugin for Toybox {
 
     type SysExMessage = ();
 
-    type BackgroundTask = ();
+    type BackgroundTask = Task<Load>;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let loaded = self.slot.loaded.clone();
+
+        Box::new(move |task| {
+            task.run(&loaded, |Load { select }| {
+                if select {
+                    params.library.select();
+                }
+                params.load_instrument()
+            })
+        })
+    }
+
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
-        // Resize buffers and perform other potentially expensive initialization
-        // operations here. The `reset()` function is always called right after
-        // this function. You can remove this function if you do not need it.
+        self.sample_rate = buffer_config.sample_rate;
+        // Restoring the state also triggers the parameter callbacks, but then
+        // the persisted library path is loaded rather than the selection.
+        // This isn't the audio thread, so the instrument is loaded right away
+        // and playback starts with it.
+        let changed = self.params.preset_change.swap(false, Ordering::Relaxed);
+        if changed || self.slot.instrument.zones.is_empty() {
+            context.execute(Task::Load(Load { select: false }));
+            self.slot
+                .replace_loaded(&mut [&mut self.voices, &mut self.accompaniment]);
+        }
+        if self.drums.zones.is_empty() {
+            match Instrument::decode(presets::DRUMS) {
+                Ok(drums) => self.drums = Box::new(drums),
+                Err(e) => nih_error!("Failed to decode the drum kit: {}", e),
+            }
+        }
+
+        // The envelope times are in seconds, so they're kept.
+        self.adsr.set_sample_rate(self.sample_rate);
+
         true
     }
 
     fn reset(&mut self) {
-        // Reset buffers and envelopes here. This can be called from the audio
-        // thread and may not allocate. You can remove this function if
-        // you do not need it.
+        self.voices.clear();
+        self.accompaniment.clear();
+        self.chord_keys.clear();
+        self.rhythm_voices.clear();
+        self.sequencer.reset();
+        self.lofi.reset();
+        for param in [
+            &self.params.gain,
+            &self.params.rhythm_level,
+            &self.params.accompaniment_level,
+        ] {
+            param.smoothed.reset(param.value());
+        }
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        for channel_samples in buffer.iter_samples() {
+        // Swap in an instrument that was loaded in the background.
+        let fade_out_samples = (FADE_OUT_S * self.sample_rate) as u32;
+        if let Some(instrument) = self.slot.swap_loaded(
+            &mut [&mut self.voices, &mut self.accompaniment],
+            fade_out_samples,
+        ) {
+            context.execute_background(Task::Drop(instrument));
+        }
+
+        // The rhythm follows the host's transport, so it only runs while the
+        // host plays and reports where it is.
+        let transport = context.transport();
+        let pattern_index = self.params.pattern.value().to_index();
+        let rhythm = match (transport.tempo, transport.pos_beats()) {
+            (Some(tempo), Some(beats))
+                if self.params.rhythm.value()
+                    && transport.playing
+                    && self.patterns[pattern_index].is_some() =>
+            {
+                Some((tempo, beats))
+            }
+            _ => None,
+        };
+        if rhythm.is_none() {
+            self.sequencer.reset();
+        }
+
+        let auto_chord = self.params.auto_chord.value();
+        let split_key = self.params.split_key.value();
+        if !auto_chord && !self.chord_keys.is_empty() {
+            // Let go of the chord that was held when auto chord was turned
+            // off.
+            self.chord_keys.clear();
+            self.change_chord(false);
+        }
+
+        let mut next_event = context.next_event();
+
+        // An instrument that was imported with an amp envelope is played with
+        // it instead of the envelope knobs.
+        let envelope = AmpEnvelope::read(&self.slot.instrument.metadata).unwrap_or(AmpEnvelope {
+            attack: self.params.attack.value(),
+            decay: self.params.decay.value(),
+            sustain: self.params.sustain.value(),
+            release: self.params.release.value(),
+        });
+        self.adsr.set_parameters(
+            envelope.attack,
+            envelope.decay,
+            envelope.sustain,
+            envelope.release,
+        );
+
+        for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
+            while let Some(event) = next_event {
+                if event.timing() > sample_id as u32 {
+                    break;
+                }
+
+                match event {
+                    NoteEvent::NoteOn { note, velocity, .. } => {
+                        if auto_chord && note as i32 <= split_key {
+                            self.chord_keys.press(note, velocity);
+                            self.change_chord(rhythm.is_some());
+                        } else {
+                            self.start_voice(note, velocity);
+                        }
+                    }
+                    NoteEvent::NoteOff { note, .. } => {
+                        if auto_chord && note as i32 <= split_key {
+                            self.chord_keys.release(note);
+                            self.change_chord(rhythm.is_some());
+                        }
+                        self.voices.note_off(note);
+                    }
+                    _ => (),
+                }
+
+                next_event = context.next_event();
+            }
+
+            if let Some((tempo, beats)) = rhythm {
+                let beats_per_sample = tempo / 60.0 / self.sample_rate as f64;
+                let beats = beats + sample_id as f64 * beats_per_sample;
+                if let Some(pattern) = &self.patterns[pattern_index] {
+                    if let Some(step) = self.sequencer.advance(pattern, beats) {
+                        let step_samples =
+                            (1.0 / (beats_per_sample * pattern.steps_per_beat as f64)) as f32;
+                        self.play_step(step, step_samples);
+                    }
+                }
+            }
+
+            let output_sample = self.voices.next_sample()
+                + self.accompaniment.next_sample()
+                    * self.params.accompaniment_level.smoothed.next()
+                + self.rhythm_voices.next_sample() * self.params.rhythm_level.smoothed.next();
+            let output_sample = self.lofi.process(&self.params.lofi, output_sample);
             let gain = self.params.gain.smoothed.next();
 
             for sample in channel_samples {
-                *sample *= gain;
+                *sample = output_sample * gain;
             }
         }
 
+        self.voices.remove_finished();
+        self.accompaniment.remove_finished();
+        self.rhythm_voices.remove_finished();
+
+        // Once the voices of the previous instrument are gone, it can go too.
+        if let Some(instrument) = self.slot.take_retired(&[&self.voices, &self.accompaniment]) {
+            context.execute_background(Task::Drop(instrument));
+        }
+
+        if self.params.preset_change.swap(false, Ordering::Relaxed) {
+            context.execute_background(Task::Load(Load { select: true }));
+        }
+
         ProcessStatus::Normal
     }
 }
 
+impl Toybox {
+    /// Starts a voice for a note, repitching the zone that covers it.
+    fn start_voice(&mut self, note: u8, velocity: f32) {
+        let pitch = (note as i32 + self.params.transpose.value()).clamp(0, 127) as u8;
+        let instrument = &self.slot.instrument;
+        if let Some(zone) = instrument.find_zone(pitch, velocity) {
+            let voice = Voice::from_zone(
+                instrument,
+                zone,
+                note,
+                pitch as f64,
+                velocity,
+                self.adsr.clone(),
+                self.sample_rate,
+            );
+            self.voices
+                .start(voice, (FADE_OUT_S * self.sample_rate) as u32);
+        }
+    }
+
+    /// Plays the chord of the held keys right away when the rhythm isn't
+    /// running. Otherwise the pattern plays it on its next chord step.
+    fn change_chord(&mut self, rhythm_running: bool) {
+        // The notes the pattern played stop by themselves, but a chord that
+        // was held before the rhythm started has to be let go of either way.
+        self.accompaniment
+            .iter_mut()
+            .filter(|v| !v.has_length())
+            .for_each(Voice::note_off);
+        if rhythm_running {
+            return;
+        }
+
+        let velocity = self.chord_keys.velocity();
+        let chord = self.chord_keys.chord();
+        for &note in chord.notes().iter().chain(&self.chord_keys.bass()) {
+            self.start_accompaniment(note, velocity, None);
+        }
+    }
+
+    /// Plays a step of the rhythm pattern. Bass and chord notes last a fixed
+    /// number of steps.
+    fn play_step(&mut self, step: usize, step_samples: f32) {
+        let pattern = self.params.pattern.value().to_index();
+        let Some(pattern) = &self.patterns[pattern] else {
+            return;
+        };
+
+        let fade_out_samples = (FADE_OUT_S * self.sample_rate) as u32;
+        for (note, steps) in &pattern.drums {
+            let velocity = steps[step];
+            if velocity <= 0.0 {
+                continue;
+            }
+            if let Some(zone) = self.drums.find_zone(*note, velocity) {
+                let adsr = Adsr::new(self.sample_rate);
+                let voice = Voice::from_zone(
+                    &self.drums,
+                    zone,
+                    *note,
+                    *note as f64,
+                    velocity,
+                    adsr,
+                    self.sample_rate,
+                );
+                self.rhythm_voices.start(voice, fade_out_samples);
+            }
+        }
+
+        let length = Some((step_samples * ACCOMPANIMENT_STEPS) as u32);
+        let (bass, chord) = (pattern.bass[step], pattern.chord[step]);
+        if bass > 0.0 {
+            if let Some(note) = self.chord_keys.bass() {
+                self.start_accompaniment(note, bass, length);
+            }
+        }
+        if chord > 0.0 {
+            for &note in self.chord_keys.chord().notes() {
+                self.start_accompaniment(note, chord, length);
+            }
+        }
+    }
+
+    /// Starts a bass or chord note, which stops by itself after `length`
+    /// samples if it's given.
+    fn start_accompaniment(&mut self, note: u8, velocity: f32, length: Option<u32>) {
+        let pitch = (note as i32 + self.params.transpose.value()).clamp(0, 127) as u8;
+        let instrument = &self.slot.instrument;
+        let Some(zone) = instrument.find_zone(pitch, velocity) else {
+            return;
+        };
+        let fade_out_samples = (FADE_OUT_S * self.sample_rate) as u32;
+        let mut voice = Voice::from_zone(
+            instrument,
+            zone,
+            pitch,
+            pitch as f64,
+            velocity,
+            self.adsr.clone(),
+            self.sample_rate,
+        );
+        if let Some(length) = length {
+            voice = voice.with_length(length, fade_out_samples);
+        }
+
+        self.accompaniment.start(voice, fade_out_samples);
+    }
+}
+
+impl ToyboxParams {
+    /// Loads the user instrument from the library, or the preset if there is
+    /// none or it can't be loaded.
+    fn load_instrument(&self) -> Option<Instrument> {
+        if let Some(instrument) = self.library.load(library::load) {
+            return Some(instrument);
+        }
+
+        let preset = self.preset.value();
+        match Instrument::decode(preset.content()) {
+            Ok(instrument) => Some(instrument),
+            Err(e) => {
+                nih_error!("Failed to decode the {} preset: {}", preset, e);
+                None
+            }
+        }
+    }
+}
+
 impl ClapPlugin for Toybox {
-    const CLAP_ID: &'static str = "com.zmann.toybox";
+    const CLAP_ID: &'static str = config::clap_id!();
     const CLAP_DESCRIPTION: Option<&'static str> = None;
     const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
     const CLAP_SUPPORT_URL: Option<&'static str> = Some(Self::URL);
 
-    // Don't forget to change these features
     const CLAP_FEATURES: &'static [ClapFeature] = &[ClapFeature::Sampler, ClapFeature::Instrument];
 }
 
 impl Vst3Plugin for Toybox {
-    const VST3_CLASS_ID: [u8; 16] = *b"zmann.c120012345";
+    const VST3_CLASS_ID: [u8; 16] = config::vst3_id!();
 
     const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
         &[Vst3SubCategory::Sampler, Vst3SubCategory::Instrument];
@@ -0,0 +1,75 @@
+//! The lo-fi character of cheap toy keyboards: a low sample rate, few bits
+//! and an overdriven amplifier.
+
+use fx::digital::bitcrush_sample;
+use fx::waveshapers::get_saturator_output;
+use nih_plug::prelude::*;
+
+#[derive(Params)]
+pub struct LoFiParams {
+    #[id = "lofi"]
+    pub enabled: BoolParam,
+    /// The resolution of the output.
+    #[id = "bits"]
+    pub bits: FloatParam,
+    /// Holds every sample for this many samples, which lowers the sample
+    /// rate.
+    #[id = "downsample"]
+    pub downsample: IntParam,
+    #[id = "drive"]
+    pub drive: FloatParam,
+}
+
+impl Default for LoFiParams {
+    fn default() -> Self {
+        Self {
+            enabled: BoolParam::new("Lo-Fi", true),
+            bits: FloatParam::new(
+                "Bits",
+                8.0,
+                FloatRange::Linear {
+                    min: 2.0,
+                    max: 16.0,
+                },
+            )
+            .with_step_size(0.1),
+            downsample: IntParam::new("Downsample", 2, IntRange::Linear { min: 1, max: 16 })
+                .with_unit("x"),
+            drive: FloatParam::new("Drive", 0.2, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+        }
+    }
+}
+
+/// The state of the lo-fi section.
+#[derive(Default)]
+pub struct LoFi {
+    /// The sample that is being held.
+    held: f32,
+    /// How many samples the held one is still repeated.
+    hold_remaining: i32,
+}
+
+impl LoFi {
+    pub fn reset(&mut self) {
+        self.held = 0.0;
+        self.hold_remaining = 0;
+    }
+
+    pub fn process(&mut self, params: &LoFiParams, input: f32) -> f32 {
+        if !params.enabled.value() {
+            return input;
+        }
+
+        if self.hold_remaining <= 0 {
+            self.held = input;
+            self.hold_remaining = params.downsample.value();
+        }
+        self.hold_remaining -= 1;
+
+        let crushed = bitcrush_sample(self.held, params.bits.value());
+        get_saturator_output(params.drive.value(), crushed)
+    }
+}
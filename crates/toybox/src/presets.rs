@@ -0,0 +1,42 @@
+use nih_plug::prelude::Enum;
+use strum::Display;
+
+#[derive(Clone, Debug, Display, Enum, Eq, Hash, PartialEq)]
+pub enum Presets {
+    Piano,
+    Organ,
+    Violin,
+    Flute,
+    Trumpet,
+    Vibraphone,
+}
+
+pub const PIANO: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "toybox/piano.zmi"));
+pub const ORGAN: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "toybox/organ.zmi"));
+pub const VIOLIN: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "toybox/violin.zmi"));
+pub const FLUTE: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "toybox/flute.zmi"));
+pub const TRUMPET: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "toybox/trumpet.zmi"));
+pub const VIBRAPHONE: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "toybox/vibraphone.zmi"));
+
+/// The drum kit of the rhythm box. Its zones are laid out like General MIDI
+/// drums.
+pub const DRUMS: &[u8] = include_bytes!(concat!(env!("SAMPLES"), "toybox/drums.zmi"));
+
+impl Presets {
+    pub fn content(&self) -> &'static [u8] {
+        match self {
+            Presets::Piano => PIANO,
+            Presets::Organ => ORGAN,
+            Presets::Violin => VIOLIN,
+            Presets::Flute => FLUTE,
+            Presets::Trumpet => TRUMPET,
+            Presets::Vibraphone => VIBRAPHONE,
+        }
+    }
+}
+
+impl Default for Presets {
+    fn default() -> Self {
+        Presets::Piano
+    }
+}
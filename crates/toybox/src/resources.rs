@@ -1,2 +0,0 @@
-// pub const TEST: &[u8] = include_bytes!(concat!(env!("SAMPLES"),
-// "toybox/TEST.txt"));
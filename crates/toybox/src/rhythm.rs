@@ -0,0 +1,226 @@
+//! The rhythm box: patterns of drum hits, bass notes and chords that follow
+//! the host's transport.
+
+use nih_plug::prelude::Enum;
+use serde::Deserialize;
+use strum::Display;
+
+/// The velocity of a step written as `x`. `X` is played at full velocity.
+const NORMAL_VELOCITY: f32 = 0.7;
+
+#[derive(Clone, Debug, Display, Enum, Eq, Hash, PartialEq)]
+pub enum Rhythms {
+    Rock,
+    Disco,
+    Waltz,
+    #[name = "Bossa Nova"]
+    #[strum(to_string = "Bossa Nova")]
+    BossaNova,
+    Swing,
+}
+
+pub const ROCK: &str = include_str!("../patterns/rock.toml");
+pub const DISCO: &str = include_str!("../patterns/disco.toml");
+pub const WALTZ: &str = include_str!("../patterns/waltz.toml");
+pub const BOSSA_NOVA: &str = include_str!("../patterns/bossa_nova.toml");
+pub const SWING: &str = include_str!("../patterns/swing.toml");
+
+impl Rhythms {
+    pub fn content(&self) -> &'static str {
+        match self {
+            Rhythms::Rock => ROCK,
+            Rhythms::Disco => DISCO,
+            Rhythms::Waltz => WALTZ,
+            Rhythms::BossaNova => BOSSA_NOVA,
+            Rhythms::Swing => SWING,
+        }
+    }
+}
+
+impl Default for Rhythms {
+    fn default() -> Self {
+        Rhythms::Rock
+    }
+}
+
+/// A pattern as it's written in its data file. Every track is a string with
+/// a character per step: `X` for an accent, `x` for a normal hit and anything
+/// else for a rest.
+///
+/// ```toml
+/// steps_per_beat = 4
+/// bass = "x.......x......."
+/// chord = "....x.......x..."
+///
+/// [[drum]]
+/// note = 36
+/// steps = "X.......X.x....."
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatternFile {
+    steps_per_beat: u32,
+    #[serde(default)]
+    bass: String,
+    #[serde(default)]
+    chord: String,
+    #[serde(default, rename = "drum")]
+    drums: Vec<DrumTrack>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DrumTrack {
+    /// The note of the drum in the drum kit.
+    note: u8,
+    steps: String,
+}
+
+/// A rhythm pattern, with the velocity of every step of its tracks. Rests
+/// have a velocity of 0.
+pub struct Pattern {
+    pub steps_per_beat: u32,
+    /// The number of steps before the pattern repeats, which is the length
+    /// of its longest track.
+    pub length: usize,
+    pub bass: Vec<f32>,
+    pub chord: Vec<f32>,
+    pub drums: Vec<(u8, Vec<f32>)>,
+}
+
+impl Pattern {
+    pub fn parse(content: &str) -> Result<Pattern, toml::de::Error> {
+        let file: PatternFile = toml::from_str(content)?;
+
+        let steps = |track: &str| -> Vec<f32> {
+            track
+                .chars()
+                .map(|step| match step {
+                    'X' => 1.0,
+                    'x' => NORMAL_VELOCITY,
+                    _ => 0.0,
+                })
+                .collect()
+        };
+        let mut pattern = Pattern {
+            steps_per_beat: file.steps_per_beat.max(1),
+            length: 0,
+            bass: steps(&file.bass),
+            chord: steps(&file.chord),
+            drums: file
+                .drums
+                .iter()
+                .map(|drum| (drum.note, steps(&drum.steps)))
+                .collect(),
+        };
+
+        // Shorter tracks rest until the end of the pattern.
+        pattern.length = pattern
+            .drums
+            .iter()
+            .map(|(_, steps)| steps.len())
+            .chain([pattern.bass.len(), pattern.chord.len(), 1])
+            .max()
+            .unwrap_or(1);
+        let length = pattern.length;
+        for steps in [&mut pattern.bass, &mut pattern.chord]
+            .into_iter()
+            .chain(pattern.drums.iter_mut().map(|(_, steps)| steps))
+        {
+            steps.resize(length, 0.0);
+        }
+
+        Ok(pattern)
+    }
+}
+
+/// Finds the steps of a pattern the transport passes.
+#[derive(Default)]
+pub struct Sequencer {
+    /// The last step that was played, counted from the start of the song.
+    last_step: Option<i64>,
+}
+
+impl Sequencer {
+    /// Forgets the last step, so the next one is played wherever the
+    /// transport starts.
+    pub fn reset(&mut self) {
+        self.last_step = None;
+    }
+
+    /// Returns the step of the pattern to play when the transport reached a
+    /// position in beats, or `None` if it's still in the last one.
+    pub fn advance(&mut self, pattern: &Pattern, beats: f64) -> Option<usize> {
+        let step = (beats * pattern.steps_per_beat as f64).floor() as i64;
+        if self.last_step == Some(step) {
+            return None;
+        }
+
+        self.last_step = Some(step);
+        Some(step.rem_euclid(pattern.length as i64) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern() -> Pattern {
+        Pattern::parse(
+            r#"
+            steps_per_beat = 2
+            bass = "X.x"
+            chord = "."
+
+            [[drum]]
+            note = 36
+            steps = "x..X"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn pads_tracks_to_the_longest_one() {
+        let pattern = pattern();
+        assert_eq!(pattern.steps_per_beat, 2);
+        assert_eq!(pattern.length, 4);
+        assert_eq!(pattern.bass, [1.0, 0.0, NORMAL_VELOCITY, 0.0]);
+        assert_eq!(pattern.chord, [0.0; 4]);
+        assert_eq!(pattern.drums, [(36, vec![NORMAL_VELOCITY, 0.0, 0.0, 1.0])]);
+    }
+
+    #[test]
+    fn parses_the_bundled_patterns() {
+        for content in [ROCK, DISCO, WALTZ, BOSSA_NOVA, SWING] {
+            let pattern = Pattern::parse(content).unwrap();
+            assert!(pattern.length > 0);
+        }
+    }
+
+    #[test]
+    fn advances_once_per_step() {
+        let pattern = pattern();
+        let mut sequencer = Sequencer::default();
+
+        assert_eq!(sequencer.advance(&pattern, 0.0), Some(0));
+        assert_eq!(sequencer.advance(&pattern, 0.25), None);
+        assert_eq!(sequencer.advance(&pattern, 0.5), Some(1));
+        // The pattern repeats after two beats.
+        assert_eq!(sequencer.advance(&pattern, 2.5), Some(1));
+        assert_eq!(sequencer.advance(&pattern, 2.5), None);
+
+        sequencer.reset();
+        assert_eq!(sequencer.advance(&pattern, 2.5), Some(1));
+    }
+
+    #[test]
+    fn counts_steps_before_the_start_of_the_song() {
+        let pattern = pattern();
+        let mut sequencer = Sequencer::default();
+
+        assert_eq!(sequencer.advance(&pattern, -0.25), Some(3));
+        assert_eq!(sequencer.advance(&pattern, -1.0), Some(2));
+        assert_eq!(sequencer.advance(&pattern, 0.0), Some(0));
+    }
+}